@@ -1,12 +1,55 @@
-#![allow(unused)]
-fn main() {
-    use criterion::{black_box, criterion_group, criterion_main, Criterion};
-    use lsof::*;
+use std::fs;
 
-    pub fn criterion_benchmark(c: &mut Criterion) {
-        c.bench_function("fib 20", |b| b.iter(|| fibonacci(black_box(20))));
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glob::glob;
+use lsof::{num_name, Data, DirFd, Filetype, NumNameBuf};
+
+/// How `/proc` used to be walked, a glob and a path per fd
+fn fd_links_glob() -> usize {
+    glob("/proc/*/fd/*")
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter_map(|p| fs::read_link(p).ok())
+        .count()
+}
+
+/// Listing and reading the links relative to the directory descriptors
+fn fd_links_dirfd() -> usize {
+    let proc_dir = DirFd::open("/proc").unwrap();
+    let mut buf = [0; 4096];
+    let mut name = NumNameBuf::default();
+    let mut count = 0;
+    for pid in proc_dir.numeric_entries().unwrap() {
+        let Ok(fd_dir) = proc_dir
+            .open_dir_at(num_name(pid, &mut name))
+            .and_then(|dir| dir.open_dir_at(c"fd"))
+        else {
+            continue;
+        };
+        for fd in fd_dir.numeric_entries().unwrap_or_default() {
+            count += usize::from(
+                fd_dir
+                    .read_link_at(num_name(fd, &mut name), &mut buf)
+                    .is_ok(),
+            );
+        }
     }
+    count
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut links = c.benchmark_group("fd links");
+    links.bench_function("glob", |b| b.iter(|| black_box(fd_links_glob())));
+    links.bench_function("dirfd", |b| b.iter(|| black_box(fd_links_dirfd())));
+    links.finish();
 
-    criterion_group!(benches, criterion_benchmark);
-    criterion_main!(benches);
+    c.bench_function("lsof all", |b| {
+        b.iter(|| black_box(Data::lsof(Filetype::All).unwrap()));
+    });
+    c.bench_function("lsof fd", |b| {
+        b.iter(|| black_box(Data::lsof(Filetype::Fd).unwrap()));
+    });
 }
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use itertools::chain;
+
+use crate::{fset, Data, Entry, FMap, FSet, FileRole, IStr};
+
+/// How an entry changed between two scans
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Change {
+    Added,
+    Removed,
+}
+
+/// How a process changed between two snapshots, see [`Data::diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProcChange {
+    pub pid: u64,
+    pub name: Option<IStr>,
+    /// `Added` if the process started, `Removed` if it exited, `None` if it ran in both
+    pub change: Option<Change>,
+    /// Files the process has in the new snapshot but not in the old one, sorted
+    pub opened: Vec<IStr>,
+    /// Files the process had in the old snapshot but not in the new one, sorted
+    pub closed: Vec<IStr>,
+}
+
+impl Entry {
+    /// What identifies an entry across scans: the process, how it uses the file and the file
+    #[must_use]
+    pub fn key(&self) -> (u64, FileRole, &str) {
+        (self.pid, self.role, &self.file)
+    }
+}
+
+/// The entries of `new` missing from `old` as `Added`, then the entries of `old` missing from `new` as `Removed`
+#[must_use]
+pub fn diff_entries<'a>(old: &'a [Entry], new: &'a [Entry]) -> Vec<(Change, &'a Entry)> {
+    let keys = |entries: &'a [Entry]| -> FSet<_> {
+        let mut keys = fset(entries.len());
+        keys.extend(entries.iter().map(Entry::key));
+        keys
+    };
+    let (old_keys, new_keys) = (keys(old), keys(new));
+    let added = new.iter().filter(|e| !old_keys.contains(&e.key()));
+    let removed = old.iter().filter(|e| !new_keys.contains(&e.key()));
+    chain!(
+        added.map(|e| (Change::Added, e)),
+        removed.map(|e| (Change::Removed, e))
+    )
+    .collect()
+}
+
+impl Data {
+    /// The processes that started, exited or opened or closed files between `self` and `new`, sorted by pid.
+    /// Both need `files_to_pid`, so bound unix socket paths count as files too
+    ///
+    /// # Errors
+    /// anyhow: `files_to_pid` not constructed for `self` or `new`
+    pub fn diff(&self, new: &Data) -> Result<Vec<ProcChange>> {
+        let context = "did not construct files_to_pid yet";
+        let old_files = self.files_to_pid().context(context)?;
+        let new_files = new.files_to_pid().context(context)?;
+        let (old_procs, new_procs) = (self.pid_to_files(), new.pid_to_files());
+
+        let proc_change = |pid: u64| {
+            let (old, new) = (old_procs.get(&pid), new_procs.get(&pid));
+            ProcChange {
+                pid,
+                name: new.or(old).and_then(|info| info.name.clone()),
+                change: match (old, new) {
+                    (None, Some(_)) => Some(Change::Added),
+                    (Some(_), None) => Some(Change::Removed),
+                    _ => None,
+                },
+                opened: Vec::new(),
+                closed: Vec::new(),
+            }
+        };
+        let started = new_procs.keys().filter(|pid| !old_procs.contains_key(pid));
+        let exited = old_procs.keys().filter(|pid| !new_procs.contains_key(pid));
+        let mut changes: FMap<u64, ProcChange> = chain!(started, exited)
+            .map(|&pid| (pid, proc_change(pid)))
+            .collect();
+        // A file was opened or closed by the pids in only one of its sets
+        for (file, info) in new_files {
+            let old = old_files.get(file).map(|info| &info.pids);
+            for &pid in info
+                .pids
+                .iter()
+                .filter(|pid| old.is_none_or(|old| !old.contains(pid)))
+            {
+                let change = changes.entry(pid).or_insert_with(|| proc_change(pid));
+                change.opened.push(file.clone());
+            }
+        }
+        for (file, info) in old_files {
+            let new = new_files.get(file).map(|info| &info.pids);
+            for &pid in info
+                .pids
+                .iter()
+                .filter(|pid| new.is_none_or(|new| !new.contains(pid)))
+            {
+                let change = changes.entry(pid).or_insert_with(|| proc_change(pid));
+                change.closed.push(file.clone());
+            }
+        }
+
+        let mut changes = changes.into_values().collect::<Vec<_>>();
+        changes.sort_unstable_by_key(|c| c.pid);
+        for c in &mut changes {
+            c.opened.sort_unstable();
+            c.closed.sort_unstable();
+        }
+        Ok(changes)
+    }
+}
@@ -0,0 +1,150 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+
+/// An open directory, the files in it are read relative to its descriptor.
+///
+/// Scanning `/proc/<pid>/fd` through a descriptor avoids building (and resolving) a path for every entry.
+#[derive(Debug)]
+pub struct DirFd {
+    dir: File,
+}
+
+/// Room for a `u64` in decimal and the nul terminator
+pub type NumNameBuf = [u8; 21];
+
+impl DirFd {
+    /// Open the directory at `path`
+    ///
+    /// # Errors
+    /// io: `path` can't be opened or is not a directory
+    pub fn open(path: &str) -> io::Result<DirFd> {
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(path)?;
+        Ok(DirFd { dir })
+    }
+
+    /// Open the directory `name` inside this one
+    ///
+    /// # Errors
+    /// io: `name` can't be opened or is not a directory
+    pub fn open_dir_at(&self, name: &CStr) -> io::Result<DirFd> {
+        let dir = self.open_at(name, libc::O_DIRECTORY)?;
+        Ok(DirFd { dir })
+    }
+
+    fn open_at(&self, name: &CStr, flags: libc::c_int) -> io::Result<File> {
+        // SAFETY: name is nul terminated, the returned descriptor is owned by the File
+        let fd = unsafe {
+            libc::openat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_CLOEXEC | flags,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd was just opened and nothing else owns it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Read the file `name` inside this directory
+    ///
+    /// # Errors
+    /// io: `name` can't be opened or is not UTF-8
+    pub fn read_to_string_at(&self, name: &CStr) -> io::Result<String> {
+        let mut content = String::new();
+        self.open_at(name, 0)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// The target of the symlink `name` inside this directory, truncated to the size of `buf`
+    ///
+    /// # Errors
+    /// io: `name` is not a symlink or can't be read
+    pub fn read_link_at<'b>(&self, name: &CStr, buf: &'b mut [u8]) -> io::Result<&'b [u8]> {
+        // SAFETY: name is nul terminated and at most buf.len() bytes are written to buf
+        let len = unsafe {
+            libc::readlinkat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        let len = usize::try_from(len).map_err(|_| io::Error::last_os_error())?;
+        Ok(&buf[..len])
+    }
+
+    /// The entries of this directory named by a number, eg. the pids in `/proc` or the fds in `/proc/<pid>/fd`
+    ///
+    /// # Errors
+    /// io: the directory can't be listed
+    pub fn numeric_entries(&self) -> io::Result<Vec<u64>> {
+        // fdopendir takes ownership of the descriptor, so give it a copy
+        let fd = self.dir.try_clone()?.into_raw_fd();
+        // SAFETY: fd is an open directory that we own
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = io::Error::last_os_error();
+            // SAFETY: fdopendir failed so fd is still ours
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        // The copy shares the offset with self, start from the top in case it was listed before
+        // SAFETY: dir is a valid stream until closedir
+        unsafe { libc::rewinddir(dir) };
+        let mut entries = Vec::new();
+        loop {
+            // SAFETY: dir is a valid stream until closedir
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            // SAFETY: d_name is nul terminated and lives until the next readdir
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            if let Some(n) = std::str::from_utf8(name.to_bytes())
+                .ok()
+                .and_then(|name| name.parse().ok())
+            {
+                entries.push(n);
+            }
+        }
+        // SAFETY: dir is not used after this, and closing it also closes fd
+        unsafe { libc::closedir(dir) };
+        Ok(entries)
+    }
+
+    /// Metadata of the directory itself
+    ///
+    /// # Errors
+    /// io: fstat failed
+    pub fn metadata(&self) -> io::Result<std::fs::Metadata> {
+        self.dir.metadata()
+    }
+}
+
+/// `n` in decimal as a nul terminated name, written into `buf` without allocating
+///
+/// # Panics
+/// Never, `buf` only holds the digits and the nul
+#[must_use]
+pub fn num_name(n: u64, buf: &mut NumNameBuf) -> &CStr {
+    let mut start = buf.len() - 1;
+    buf[start] = 0;
+    let mut n = n;
+    loop {
+        start -= 1;
+        buf[start] = b'0' + u8::try_from(n % 10).expect("A digit");
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    CStr::from_bytes_with_nul(&buf[start..]).expect("Only digits and the nul")
+}
@@ -0,0 +1,125 @@
+use std::fs::read_to_string;
+
+use bitflags::bitflags;
+
+use crate::IStr;
+
+// https://man7.org/linux/man-pages/man5/proc_pid_fdinfo.5.html
+
+bitflags! {
+    /// The `flags` field of `/proc/<pid>/fdinfo/<fd>`, the `O_*` flags given to open(2)
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct OpenFlags: u32 {
+        const WRONLY = 0o1;
+        const RDWR = 0o2;
+        const CREAT = 0o100;
+        const EXCL = 0o200;
+        const NOCTTY = 0o400;
+        const TRUNC = 0o1000;
+        const APPEND = 0o2000;
+        const NONBLOCK = 0o4000;
+        const DSYNC = 0o10_000;
+        const ASYNC = 0o20_000;
+        const DIRECT = 0o40_000;
+        const LARGEFILE = 0o100_000;
+        const DIRECTORY = 0o200_000;
+        const NOFOLLOW = 0o400_000;
+        const NOATIME = 0o1_000_000;
+        const CLOEXEC = 0o2_000_000;
+        const PATH = 0o10_000_000;
+
+        // Keep any bits we don't know about
+        const _ = !0;
+    }
+}
+
+/// Access mode of a descriptor, the `O_ACCMODE` bits of its flags
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessMode {
+    #[default]
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// An open file descriptor of a process, from `/proc/<pid>/fd/<fd>` and `/proc/<pid>/fdinfo/<fd>`
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OpenFile {
+    pub fd: u32,
+    /// The link target of the descriptor
+    pub file: IStr,
+    /// File offset
+    pub pos: u64,
+    pub flags: OpenFlags,
+    /// Id of the mount containing the file (since Linux 3.15)
+    pub mnt_id: Option<u64>,
+    /// The file was unlinked while open, its ` (deleted)` suffix is stripped from `file`
+    pub deleted: bool,
+}
+
+impl OpenFlags {
+    #[must_use]
+    pub fn access_mode(self) -> AccessMode {
+        if self.contains(OpenFlags::RDWR) {
+            AccessMode::ReadWrite
+        } else if self.contains(OpenFlags::WRONLY) {
+            AccessMode::Write
+        } else {
+            AccessMode::Read
+        }
+    }
+}
+
+impl AccessMode {
+    /// The mode character of lsof's FD column
+    #[must_use]
+    pub const fn as_char(self) -> char {
+        match self {
+            AccessMode::Read => 'r',
+            AccessMode::Write => 'w',
+            AccessMode::ReadWrite => 'u',
+        }
+    }
+}
+
+impl OpenFile {
+    #[must_use]
+    pub fn access_mode(&self) -> AccessMode {
+        self.flags.access_mode()
+    }
+    /// The lsof FD column, eg. `3r`, `4w`, `5u`
+    #[must_use]
+    pub fn fd_column(&self) -> String {
+        format!("{}{}", self.fd, self.access_mode().as_char())
+    }
+}
+
+/// Read `/proc/<pid>/fdinfo/<fd>` into `open_file`, leaving the defaults if it can't be read
+#[tracing::instrument(level = "trace", skip(open_file))]
+pub fn read_fdinfo(proc_path_str: &str, open_file: &mut OpenFile) {
+    let path = format!("{proc_path_str}/fdinfo/{}", open_file.fd);
+    if let Ok(content) = read_to_string(path) {
+        parse_fdinfo(&content, open_file);
+    }
+}
+
+/// Parse the `pos`, `flags` and `mnt_id` fields of an fdinfo file
+pub fn parse_fdinfo(content: &str, open_file: &mut OpenFile) {
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "pos" => open_file.pos = value.parse().unwrap_or_default(),
+            "flags" => {
+                open_file.flags =
+                    OpenFlags::from_bits_retain(u32::from_str_radix(value, 8).unwrap_or_default());
+            }
+            "mnt_id" => open_file.mnt_id = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
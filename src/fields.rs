@@ -0,0 +1,175 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use bitflags::bitflags;
+use itertools::Itertools;
+
+use crate::{user_column, Data, Entry};
+
+// https://man7.org/linux/man-pages/man8/lsof.8.html#OUTPUT_FOR_OTHER_PROGRAMS
+
+bitflags! {
+    /// The fields of lsof's `-F` output, selected by their identifier characters
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Fields: u16 {
+        /// `p`, always selected
+        const PID = 1 << 0;
+        /// `c`
+        const COMMAND = 1 << 1;
+        /// `u`
+        const UID = 1 << 2;
+        /// `L`
+        const LOGIN = 1 << 3;
+        /// `R`
+        const PPID = 1 << 4;
+        /// `g`
+        const PGID = 1 << 5;
+        /// `f`, selected along with any other file field
+        const FD = 1 << 6;
+        /// `a`
+        const ACCESS = 1 << 7;
+        /// `t`
+        const TYPE = 1 << 8;
+        /// `o`
+        const OFFSET = 1 << 9;
+        /// `n`
+        const NAME = 1 << 10;
+        /// `0`, end fields with NUL instead of a newline
+        const NUL = 1 << 15;
+
+        const PROCESS = Self::PID.bits()
+            | Self::COMMAND.bits()
+            | Self::UID.bits()
+            | Self::LOGIN.bits()
+            | Self::PPID.bits()
+            | Self::PGID.bits();
+        const FILE = Self::FD.bits()
+            | Self::ACCESS.bits()
+            | Self::TYPE.bits()
+            | Self::OFFSET.bits()
+            | Self::NAME.bits();
+    }
+}
+
+impl Fields {
+    #[must_use]
+    pub fn from_char(c: char) -> Option<Fields> {
+        Some(match c {
+            'p' => Fields::PID,
+            'c' => Fields::COMMAND,
+            'u' => Fields::UID,
+            'L' => Fields::LOGIN,
+            'R' => Fields::PPID,
+            'g' => Fields::PGID,
+            'f' => Fields::FD,
+            'a' => Fields::ACCESS,
+            't' => Fields::TYPE,
+            'o' => Fields::OFFSET,
+            'n' => Fields::NAME,
+            '0' => Fields::NUL,
+            _ => return None,
+        })
+    }
+}
+
+/// The argument of `-F`, eg. `pcfn`, every field if empty
+impl FromStr for Fields {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut fields = Fields::PID;
+        for c in s.chars() {
+            let Some(field) = Fields::from_char(c) else {
+                bail!("unsupported field {c}");
+            };
+            fields |= field;
+        }
+        // Only a bare `-F` selects everything, `-F p` is just the pids
+        if s.chars().all(|c| c == '0') {
+            fields |= Fields::PROCESS | Fields::FILE;
+        }
+        if fields.intersects(Fields::FILE) {
+            fields |= Fields::FD;
+        }
+        Ok(fields)
+    }
+}
+
+impl Data {
+    /// Write lsof's `-F` output, a process set for each process followed by a file set for
+    /// each of its files, ordered by pid
+    ///
+    /// # Errors
+    /// If writing to `out` fails
+    pub fn write_fields(self, fields: Fields, out: &mut impl Write) -> io::Result<()> {
+        let end = if fields.contains(Fields::NUL) {
+            "\0"
+        } else {
+            "\n"
+        };
+        let unix_sockets = self.unix_sockets;
+        for (pid, info) in self
+            .pid_to_files
+            .into_iter()
+            .sorted_unstable_by_key(|(pid, _)| *pid)
+        {
+            write!(out, "p{pid}{end}")?;
+            let stat = info.stat().cloned();
+            // From the process, which may have no files
+            if let (true, Some(name)) = (fields.contains(Fields::COMMAND), &info.name) {
+                write!(out, "c{name}{end}")?;
+            }
+            if let (true, Some(uid)) = (fields.contains(Fields::UID), info.uid) {
+                write!(out, "u{uid}{end}")?;
+            }
+            if fields.contains(Fields::LOGIN) {
+                write!(out, "L{}{end}", user_column(info.uid))?;
+            }
+            if let (true, Some(stat)) = (fields.contains(Fields::PPID), &stat) {
+                write!(out, "R{}{end}", stat.ppid)?;
+            }
+            if let (true, Some(stat)) = (fields.contains(Fields::PGID), &stat) {
+                write!(out, "g{}{end}", stat.pgrp)?;
+            }
+            if end == "\0" {
+                writeln!(out)?;
+            }
+            if !fields.intersects(Fields::FILE) {
+                continue;
+            }
+            for entry in Entry::from((pid, info), &unix_sockets) {
+                write_file_set(&entry, fields, end, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_file_set(
+    entry: &Entry,
+    fields: Fields,
+    end: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match &entry.fd {
+        Some(fd) => write!(out, "f{}{end}", fd.fd)?,
+        None => write!(out, "f{}{end}", entry.role)?,
+    }
+    if let (true, Some(fd)) = (fields.contains(Fields::ACCESS), &entry.fd) {
+        write!(out, "a{}{end}", fd.access_mode().as_char())?;
+    }
+    if fields.contains(Fields::TYPE) {
+        write!(out, "t{}{end}", entry.file_type())?;
+    }
+    if let (true, Some(fd)) = (fields.contains(Fields::OFFSET), &entry.fd) {
+        write!(out, "o0t{}{end}", fd.pos)?;
+    }
+    if fields.contains(Fields::NAME) {
+        write!(out, "n{}{end}", entry.name_column())?;
+    }
+    if end == "\0" {
+        writeln!(out)?;
+    }
+    Ok(())
+}
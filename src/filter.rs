@@ -0,0 +1,53 @@
+use regex::Regex;
+
+use crate::UserFilter;
+
+/// Which processes and files to keep while scanning `/proc`.
+///
+/// Processes are checked before their fd directory is read,
+/// so a narrow filter also makes the scan faster.
+#[derive(Default, Debug, Clone)]
+pub struct Filter {
+    pub pid: Option<u64>,
+    /// Matched against the process name
+    pub proc_regex: Option<Regex>,
+    /// Matched against each file, processes without a matching file are dropped
+    pub file_regex: Option<Regex>,
+    pub user: Option<UserFilter>,
+    /// Only keep files that were unlinked while in use, like lsof's `+L1`
+    pub deleted: bool,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pid.is_none()
+            && self.proc_regex.is_none()
+            && self.file_regex.is_none()
+            && self.user.is_none()
+            && !self.deleted
+    }
+    /// Whether files are filtered, not only processes
+    #[must_use]
+    pub fn filters_files(&self) -> bool {
+        self.file_regex.is_some() || self.deleted
+    }
+    #[must_use]
+    pub fn matches_pid(&self, pid: u64) -> bool {
+        self.pid.is_none_or(|p| p == pid)
+    }
+    #[must_use]
+    pub fn matches_proc(&self, name: Option<&str>) -> bool {
+        self.proc_regex
+            .as_ref()
+            .is_none_or(|re| name.is_some_and(|name| re.is_match(name)))
+    }
+    #[must_use]
+    pub fn matches_user(&self, uid: Option<u32>) -> bool {
+        self.user.as_ref().is_none_or(|user| user.matches(uid))
+    }
+    #[must_use]
+    pub fn matches_file(&self, file: &str, deleted: bool) -> bool {
+        (deleted || !self.deleted) && self.file_regex.as_ref().is_none_or(|re| re.is_match(file))
+    }
+}
@@ -0,0 +1,123 @@
+//! A fake procfs in a temporary directory, so tests don't depend on the processes of the machine.
+//!
+//! Shared by the unit tests and the cli tests (through `#[path]`), so it only uses std and tempfile.
+#![allow(dead_code)]
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+const NET_HEADER: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n";
+const UNIX_HEADER: &str = "Num       RefCount Protocol Flags    Type St Inode Path\n";
+
+/// The root of a fake procfs, removed when dropped
+pub struct ProcFixture {
+    dir: TempDir,
+}
+
+/// A process of a [`ProcFixture`], each file is written when it is added
+pub struct FakeProc {
+    path: PathBuf,
+}
+
+impl ProcFixture {
+    /// An empty proc root with empty socket tables, booted at 1700000000
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("Create a temporary directory");
+        let net = dir.path().join("net");
+        fs::create_dir(&net).unwrap();
+        for table in ["tcp", "tcp6", "udp", "udp6"] {
+            fs::write(net.join(table), NET_HEADER).unwrap();
+        }
+        fs::write(net.join("unix"), UNIX_HEADER).unwrap();
+        fs::write(
+            dir.path().join("stat"),
+            "cpu  0 0 0 0 0 0 0 0 0 0\nbtime 1700000000\n",
+        )
+        .unwrap();
+        ProcFixture { dir }
+    }
+
+    /// The path to give to `--proc-root`
+    pub fn root(&self) -> &str {
+        self.dir
+            .path()
+            .to_str()
+            .expect("The temporary directory is UTF-8")
+    }
+
+    /// A sleeping process with a `stat`, `status` and empty `maps`, `fd` and `fdinfo`
+    pub fn process(&self, pid: u64, name: &str) -> FakeProc {
+        let path = self.dir.path().join(pid.to_string());
+        fs::create_dir_all(path.join("fd")).unwrap();
+        fs::create_dir_all(path.join("fdinfo")).unwrap();
+        fs::write(
+            path.join("stat"),
+            format!("{pid} ({name}) S 1 {pid} {pid} 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 1 0 250 10000 200 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0\n"),
+        )
+        .unwrap();
+        fs::write(
+            path.join("status"),
+            format!("Name:\t{name}\nState:\tS (sleeping)\nTgid:\t{pid}\nPid:\t{pid}\nPPid:\t1\nThreads:\t1\n"),
+        )
+        .unwrap();
+        fs::write(path.join("maps"), "").unwrap();
+        FakeProc { path }
+    }
+
+    /// Append a row to one of the socket tables, eg. `unix` or `tcp`
+    pub fn net(&self, table: &str, row: &str) -> &Self {
+        append(&self.dir.path().join("net").join(table), row);
+        self
+    }
+}
+
+impl FakeProc {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn cwd(&self, target: &str) -> &Self {
+        symlink(target, self.path.join("cwd")).unwrap();
+        self
+    }
+    pub fn root(&self, target: &str) -> &Self {
+        symlink(target, self.path.join("root")).unwrap();
+        self
+    }
+    pub fn exe(&self, target: &str) -> &Self {
+        symlink(target, self.path.join("exe")).unwrap();
+        self
+    }
+    /// An open descriptor, read only at offset 0
+    pub fn fd(&self, fd: u32, target: &str) -> &Self {
+        self.fd_with(fd, target, 0, 0o100_000)
+    }
+    /// An open descriptor with the `pos` and octal `flags` of its fdinfo
+    pub fn fd_with(&self, fd: u32, target: &str, pos: u64, flags: u32) -> &Self {
+        symlink(target, self.path.join("fd").join(fd.to_string())).unwrap();
+        fs::write(
+            self.path.join("fdinfo").join(fd.to_string()),
+            format!("pos:\t{pos}\nflags:\t0{flags:o}\nmnt_id:\t1\n"),
+        )
+        .unwrap();
+        self
+    }
+    /// A readable mapping of `file`
+    pub fn map(&self, file: &str) -> &Self {
+        append(
+            &self.path.join("maps"),
+            &format!(
+                "7f0000000000-7f0000001000 r--p 00000000 fe:00 1234                       {file}"
+            ),
+        );
+        self
+    }
+}
+
+fn append(path: &Path, line: &str) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    writeln!(file, "{line}").unwrap();
+}
@@ -1,52 +1,147 @@
 #![warn(clippy::pedantic)]
 #![feature(const_trait_impl)]
-#![feature(hash_raw_entry)]
 #![feature(iter_collect_into)]
 #![feature(anonymous_lifetime_in_impl_trait)]
-use anyhow::{anyhow, Context, Result};
-use glob::glob;
+use anyhow::{anyhow, bail, Context, Result};
 use itertools::{chain, Itertools};
-use rayon::iter::{
-    IntoParallelIterator, IntoParallelRefIterator, ParallelBridge, ParallelIterator,
-};
 use std::error::Error;
 use std::fmt::Display;
-use std::fs::read_to_string;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
 use std::str::FromStr;
-use std::{fs, path::Component};
+use std::sync::{Arc, OnceLock};
 
 mod utils;
 pub use utils::*;
 mod procstat;
 pub use procstat::*;
-
-// PERF: leak all the Strings for fun and profits
-// No more String
+mod net;
+pub use net::*;
+mod fdinfo;
+pub use fdinfo::*;
+mod users;
+pub use users::*;
+mod filter;
+pub use filter::*;
+mod fields;
+pub use fields::*;
+mod table;
+pub use table::*;
+mod dirfd;
+pub use dirfd::*;
+mod scanner;
+pub use scanner::*;
+mod diff;
+pub use diff::*;
+mod snapshot;
+pub use snapshot::*;
+mod pinned;
+pub use pinned::*;
+mod recover;
+pub use recover::*;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Data {
     // pid => info
     pid_to_files: FMap<u64, ProcInfo>,
     // file => pid
-    files_to_pid: Option<FMap<String, FdInfo>>, // PERF: leak this
+    files_to_pid: Option<FMap<IStr, FdInfo>>,
+    // socket inode => unix socket
+    unix_sockets: FMap<u64, UnixSocket>,
+    // The names and files of the scan, shared with clones
+    #[cfg_attr(feature = "serde", serde(skip))]
+    strings: Arc<Interner>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    proc_root: ProcRoot,
+    // The keys of files_to_pid sorted, for prefix queries, built on first use
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dir_index: OnceLock<Vec<IStr>>,
 }
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Proc {
     pub pid: u64,
     pub info: ProcInfo,
 }
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ProcInfo {
-    pub name: Option<&'static str>,
-    pub files: FSet<&'static str>,
+    pub name: Option<IStr>,
+    /// Owner of `/proc/<pid>`, the effective uid of the process
+    pub uid: Option<u32>,
+    pub files: FSet<IStr>,
+    /// The open descriptors, a subset of `files`
+    pub fds: Vec<OpenFile>,
+    /// Files used other than through a descriptor (cwd, root, exe and mappings), a subset of `files`
+    pub records: Vec<FileRecord>,
+    /// The procfs this process was read from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub proc_root: ProcRoot,
+    /// The pid this was read for, whose stat and status are cached
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pid: u64,
+    /// Read on first access, see [`ProcInfo::stat`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stat: StatCell,
+    /// Read on first access, see [`ProcInfo::status`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    status: OnceLock<Option<Status>>,
+}
+/// A `/proc/<pid>/stat` read on first access and shared with the clones,
+/// so the entries of a process read it once. Ignored when comparing entries
+#[derive(Default, Debug, Clone)]
+struct StatCell(Arc<OnceLock<Option<Stat>>>);
+impl StatCell {
+    fn get(&self, proc_root: &ProcRoot, pid: u64) -> Option<&Stat> {
+        self.0
+            .get_or_init(|| get_pid_stat(proc_root.live_pid_path(pid)?))
+            .as_ref()
+    }
+}
+impl PartialEq for StatCell {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+impl Eq for StatCell {}
+impl std::hash::Hash for StatCell {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+/// How a process is using a file, the lsof FD column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FileRole {
+    /// Current working directory
+    Cwd,
+    /// Root directory
+    Rtd,
+    /// Program text (the executable)
+    Txt,
+    /// Memory-mapped file
+    Mem,
+    /// Memory-mapped file that has been deleted
+    Del,
+    /// Open file descriptor
+    Fd(u32),
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FileRecord {
+    pub role: FileRole,
+    pub file: IStr,
+    /// The file was unlinked while in use, its ` (deleted)` suffix is stripped from `file`
+    pub deleted: bool,
 }
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Fd {
     pub info: FdInfo,
     pub name: String,
 }
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FdInfo {
     pub pids: FSet<u64>, // PERF: can be Vec because small?
 }
@@ -59,6 +154,11 @@ pub enum Filetype {
     Socket, // TODO: Not really used
     File,   // TODO: Not really used
     Extension(&'static str),
+    Cwd,
+    Rtd,
+    Txt,
+    Fd,
+    Deleted,
 }
 
 ///get all infomation
@@ -76,16 +176,17 @@ pub fn lsof_file(path: String) -> Result<Vec<Result<Proc, u64>>> {
         println!("File type {file_type:?}");
     }
 
-    let data = Data::lsof(Filetype::All)?;
+    let mut data = Data::lsof(Filetype::All)?;
+    data.invert_pid_to_files(&path);
 
     data.find(&path)
 }
-///get socket port used by process
+///get processes bound to or connected on a tcp/udp port
 #[tracing::instrument(level = "info")]
-pub fn lsof_port(port: String) -> Result<Vec<Result<Proc, u64>>> {
-    let path = format!("socket:[{port}]");
-    let data = Data::lsof(Filetype::Socket)?;
-    data.find(&path)
+pub fn lsof_port(port: u16) -> Result<Vec<Result<Proc, u64>>> {
+    let mut data = Data::lsof(Filetype::Socket)?;
+    data.invert_pid_to_files("");
+    data.find_port(port)
 }
 
 impl From<(u64, ProcInfo)> for Proc {
@@ -98,6 +199,45 @@ impl From<Proc> for ProcInfo {
         info
     }
 }
+impl ProcInfo {
+    /// The parsed `/proc/<pid>/stat` of this process, read the first time it is needed, `None` for a loaded snapshot
+    #[must_use]
+    pub fn stat(&self) -> Option<&Stat> {
+        self.stat.get(&self.proc_root, self.pid)
+    }
+    /// The parsed `/proc/<pid>/status` of this process, read the first time it is needed
+    #[must_use]
+    pub fn status(&self) -> Option<&Status> {
+        self.status
+            .get_or_init(|| get_pid_status(self.proc_root.live_pid_path(self.pid)?))
+            .as_ref()
+    }
+}
+impl ProcInfo {
+    /// Only keep the records and descriptors whose role, file and deleted flag match `f`
+    pub fn retain_files(&mut self, mut f: impl FnMut(FileRole, &IStr, bool) -> bool) {
+        let ProcInfo {
+            files,
+            fds,
+            records,
+            ..
+        } = self;
+        records.retain(|r| f(r.role, &r.file, r.deleted));
+        fds.retain(|fd| f(FileRole::Fd(fd.fd), &fd.file, fd.deleted));
+        files.retain(|file| {
+            records.iter().any(|r| r.file == *file) || fds.iter().any(|f| f.file == *file)
+        });
+    }
+}
+impl Proc {
+    /// Read the memory mappings of this process
+    #[must_use]
+    pub fn maps(&self) -> Vec<MapEntry> {
+        self.proc_root
+            .live_pid_path(self.pid)
+            .map_or_else(Vec::new, get_pid_maps)
+    }
+}
 impl std::ops::Deref for Proc {
     type Target = ProcInfo;
     fn deref(&self) -> &Self::Target {
@@ -121,6 +261,12 @@ impl std::ops::Deref for Fd {
     }
 }
 
+impl std::fmt::Debug for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.debug_fmt(f)
+    }
+}
+
 impl Default for Data {
     fn default() -> Self {
         Self::new()
@@ -129,7 +275,24 @@ impl Default for Data {
 
 impl Filetype {
     const fn includes_mem(self) -> bool {
-        matches!(self, Filetype::Mem) || matches!(self, Filetype::All)
+        matches!(self, Filetype::Mem | Filetype::Deleted | Filetype::All)
+    }
+    const fn includes_fd(self) -> bool {
+        !matches!(
+            self,
+            Filetype::Mem | Filetype::Deleted | Filetype::Cwd | Filetype::Rtd | Filetype::Txt
+        )
+    }
+    #[must_use]
+    pub const fn includes_role(self, role: FileRole) -> bool {
+        match role {
+            FileRole::Cwd => matches!(self, Filetype::Cwd | Filetype::All),
+            FileRole::Rtd => matches!(self, Filetype::Rtd | Filetype::All),
+            FileRole::Txt => matches!(self, Filetype::Txt | Filetype::All),
+            FileRole::Mem => matches!(self, Filetype::Mem | Filetype::All),
+            FileRole::Del => matches!(self, Filetype::Mem | Filetype::Deleted | Filetype::All),
+            FileRole::Fd(_) => self.includes_fd(),
+        }
     }
     const fn includes_socket(self) -> bool {
         matches!(self, Filetype::Socket) || matches!(self, Filetype::All)
@@ -159,12 +322,29 @@ impl FromStr for Filetype {
             "mem" => Filetype::Mem,
             "socket" => Filetype::Socket,
             "file" => Filetype::File,
+            "cwd" => Filetype::Cwd,
+            "rtd" => Filetype::Rtd,
+            "txt" => Filetype::Txt,
+            "fd" => Filetype::Fd,
+            "del" | "deleted" => Filetype::Deleted,
             s if s.starts_with('.') => Filetype::Extension(s.trim_start_matches('.').leak_str()),
             _ => Err(BadFiletypeStr)?,
         })
     }
 }
 impl Error for BadFiletypeStr {}
+impl Display for FileRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileRole::Cwd => write!(f, "cwd"),
+            FileRole::Rtd => write!(f, "rtd"),
+            FileRole::Txt => write!(f, "txt"),
+            FileRole::Mem => write!(f, "mem"),
+            FileRole::Del => write!(f, "DEL"),
+            FileRole::Fd(fd) => write!(f, "{fd}"),
+        }
+    }
+}
 impl Display for BadFiletypeStr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Bad filetype arg")
@@ -172,23 +352,144 @@ impl Display for BadFiletypeStr {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Entry {
     pub pid: u64,
-    pub proc: &'static str,
-    pub file: &'static str,
+    pub proc: IStr,
+    pub uid: Option<u32>,
+    /// The user name, or the uid if it has no name
+    pub user: IStr,
+    pub file: IStr,
+    /// The bound path of a unix socket
+    pub socket_path: Option<IStr>,
+    pub role: FileRole,
+    /// The file was unlinked while in use
+    pub deleted: bool,
+    /// The descriptor, if `role` is `FileRole::Fd`
+    pub fd: Option<OpenFile>,
+    /// The procfs the process was read from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub proc_root: ProcRoot,
+    /// Shared with the process, see [`Entry::stat`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stat: StatCell,
 }
 impl Entry {
-    fn from((pid, proc): (u64, ProcInfo)) -> impl Iterator<Item = Self> {
-        let name = proc.name.map_or("<noname>", StrLeakExt::leak_str);
-        proc.files.into_iter().map(move |f| Self {
+    fn from(
+        (pid, proc): (u64, ProcInfo),
+        unix_sockets: &FMap<u64, UnixSocket>,
+    ) -> impl Iterator<Item = Self> + '_ {
+        let ProcInfo {
+            name,
+            uid,
+            fds,
+            records,
+            proc_root,
+            stat,
+            ..
+        } = proc;
+        let name = name.unwrap_or_else(|| "<noname>".into());
+        let user = user_column(uid);
+        let records = records.into_iter().map(|r| (r, None));
+        let fds = fds.into_iter().map(|fd| {
+            let record = FileRecord {
+                role: FileRole::Fd(fd.fd),
+                file: fd.file.clone(),
+                deleted: fd.deleted,
+            };
+            (record, Some(fd))
+        });
+        chain!(records, fds).map(move |(record, fd)| Self {
             pid,
-            proc: name,
-            file: f.leak_str(),
+            proc: name.clone(),
+            uid,
+            user: user.clone(),
+            socket_path: unix_socket_path(unix_sockets, &record.file).cloned(),
+            file: record.file,
+            role: record.role,
+            deleted: record.deleted,
+            fd,
+            proc_root: proc_root.clone(),
+            stat: stat.clone(),
         })
     }
+    /// See [`file_kind`]
+    #[must_use]
+    pub fn kind(&self) -> &str {
+        file_kind(&self.file)
+    }
     #[must_use]
-    pub fn get_ext(&self) -> &'static str {
-        self.file.rsplit_once('.').unwrap_or((self.file, "")).1
+    pub fn get_ext(&self) -> &str {
+        self.file.rsplit_once('.').unwrap_or((&self.file, "")).1
+    }
+    /// The lsof FD column, eg. `cwd`, `mem` or `3r`
+    #[must_use]
+    pub fn fd_column(&self) -> String {
+        self.fd
+            .as_ref()
+            .map_or_else(|| self.role.to_string(), OpenFile::fd_column)
+    }
+    /// The socket path for bound unix sockets, otherwise the file
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.socket_path.as_deref().unwrap_or(&self.file)
+    }
+    /// The lsof NAME column, [`Entry::name`] marked ` (deleted)` if the file was unlinked
+    #[must_use]
+    pub fn name_column(&self) -> String {
+        if self.deleted {
+            format!("{} (deleted)", self.name())
+        } else {
+            self.name().to_owned()
+        }
+    }
+    /// The lsof TYPE column, eg. `REG`, `DIR`, `CHR`, `FIFO` or `sock`
+    #[must_use]
+    pub fn file_type(&self) -> &'static str {
+        self.file_type_with(self.metadata().as_ref())
+    }
+    pub(crate) fn file_type_with(&self, meta: Option<&fs::Metadata>) -> &'static str {
+        match self.kind() {
+            "socket" if self.socket_path.is_some() => "unix",
+            "socket" => "sock",
+            "pipe" => "FIFO",
+            "anon_inode" => "a_inode",
+            _ => meta.map_or("unknown", |meta| {
+                let ty = meta.file_type();
+                if ty.is_dir() {
+                    "DIR"
+                } else if ty.is_file() {
+                    "REG"
+                } else if ty.is_char_device() {
+                    "CHR"
+                } else if ty.is_block_device() {
+                    "BLK"
+                } else if ty.is_fifo() {
+                    "FIFO"
+                } else if ty.is_socket() {
+                    "sock"
+                } else {
+                    "unknown"
+                }
+            }),
+        }
+    }
+    /// Metadata of the open file, read through the links in `/proc/<pid>`
+    /// so it also works for sockets, pipes and deleted files. `None` for a loaded snapshot
+    #[must_use]
+    pub fn metadata(&self) -> Option<fs::Metadata> {
+        if !self.proc_root.is_live() {
+            return None;
+        }
+        match self.proc_root.link_path(self.pid, self.role) {
+            Some(link) => fs::metadata(link).ok(),
+            None => fs::metadata(&*self.file).ok(),
+        }
+    }
+    /// The parsed `/proc/<pid>/stat` of the process, read once for all its entries
+    #[must_use]
+    pub fn stat(&self) -> Option<&Stat> {
+        self.stat.get(&self.proc_root, self.pid)
     }
 }
 
@@ -197,6 +498,7 @@ impl Data {
         f.debug_struct("Data")
             .field("pid_to_files", &self.pid_to_files)
             .field("files_to_pid", &self.files_to_pid)
+            .field("unix_sockets", &self.unix_sockets)
             .finish()
     }
 
@@ -204,27 +506,44 @@ impl Data {
         Data {
             pid_to_files: fmap(0),
             files_to_pid: None,
+            unix_sockets: fmap(0),
+            strings: Arc::default(),
+            proc_root: ProcRoot::default(),
+            dir_index: OnceLock::new(),
         }
     }
 
+    /// The strings shared by the processes and files of this scan
+    #[must_use]
+    pub fn strings(&self) -> &Interner {
+        &self.strings
+    }
+
     pub fn flattened(self) -> impl Iterator<Item = Entry> {
-        self.pid_to_files.into_iter().flat_map(Entry::from)
+        let Data {
+            pid_to_files,
+            unix_sockets,
+            ..
+        } = self;
+        pid_to_files
+            .into_iter()
+            .flat_map(move |p| Entry::from(p, &unix_sockets).collect_vec())
     }
 
-    fn files_to_pid_mut(&mut self) -> &mut FMap<String, FdInfo> {
+    fn files_to_pid_mut(&mut self) -> &mut FMap<IStr, FdInfo> {
         self.as_mut().1
     }
-    fn as_mut(&mut self) -> (&mut FMap<u64, ProcInfo>, &mut FMap<String, FdInfo>) {
+    fn as_mut(&mut self) -> (&mut FMap<u64, ProcInfo>, &mut FMap<IStr, FdInfo>) {
         (
             &mut self.pid_to_files,
             self.files_to_pid.get_or_insert_with(|| fmap(0)),
         )
     }
-    fn file_to_pid_insert(&mut self, fname: &str, pid: u64) {
+    fn file_to_pid_insert(&mut self, fname: &IStr, pid: u64) {
         file_to_pid_insert(self.files_to_pid_mut(), fname, pid);
     }
     // #[tracing::instrument(skip(self, i), level = "trace")]
-    fn file_to_pid_extend(&mut self, i: impl IntoIterator<Item = (&str, u64)>) {
+    fn file_to_pid_extend(&mut self, i: impl IntoIterator<Item = (&IStr, u64)>) {
         file_to_pid_extend(self.files_to_pid_mut(), i);
     }
 
@@ -235,41 +554,83 @@ impl Data {
     }
     // #[tracing::instrument(level = "info")]
     pub fn lsof(target_filetype: Filetype) -> Result<Data> {
-        let mut data = Data::new();
-        // PERF: this glob can just be a read_dir
-        let proc_paths = glob("/proc/*")?;
-        // PERF: parallelize
-        data.pid_to_files = proc_paths
-            // .collect_vec()
-            // .into_par_iter()
-            .par_bridge()
-            .filter_map(|proc| {
-                let proc = proc.ok()?;
-                let (_pid_str, pid) = extract_pid_from_path(&proc);
-                Some((pid.ok()?, proc.into_os_string().into_string().unwrap()))
-            })
-            .map(|(pid, proc_path_str)| {
-                //get process other info
-                let name = get_pid_name(proc_path_str.clone());
-
-                let (cap, files) = get_files_info(target_filetype, proc_path_str);
-                let mut fileset = fset(cap.min(1));
-                files.collect_into(&mut fileset);
-
-                (
-                    pid,
-                    ProcInfo {
-                        name,
-                        files: fileset,
-                    },
-                )
-            })
-            .collect();
-        Ok(data)
+        Self::lsof_filtered(target_filetype, &Filter::default())
+    }
+    /// Scan `/proc`, only keeping the processes and files matching `filter`
+    ///
+    /// # Errors
+    /// anyhow: `/proc` could not be listed
+    pub fn lsof_filtered(target_filetype: Filetype, filter: &Filter) -> Result<Data> {
+        Scanner::new()
+            .filetype(target_filetype)
+            .filter(filter.clone())
+            .scan()
+    }
+
+    /// The procfs this was scanned from
+    #[must_use]
+    pub fn proc_root(&self) -> &ProcRoot {
+        &self.proc_root
+    }
+
+    /// Only keep the processes matching `f`
+    pub fn retain(&mut self, mut f: impl FnMut(u64, &ProcInfo) -> bool) {
+        self.pid_to_files.retain(|&pid, info| f(pid, info));
+        self.dir_index.take();
+        let Data {
+            pid_to_files,
+            files_to_pid,
+            ..
+        } = self;
+        if let Some(files_to_pid) = files_to_pid {
+            files_to_pid.retain(|_, info| {
+                info.pids.retain(|pid| pid_to_files.contains_key(pid));
+                !info.pids.is_empty()
+            });
+        }
+    }
+
+    /// Drop the processes and files a scan with `target_filetype` and `filter` would have skipped,
+    /// eg. for a loaded snapshot
+    pub fn retain_matching(&mut self, target_filetype: Filetype, filter: &Filter) {
+        self.retain(|pid, info| {
+            filter.matches_pid(pid)
+                && filter.matches_proc(info.name.as_deref())
+                && filter.matches_user(info.uid)
+        });
+        let mut dropped = false;
+        for info in self.pid_to_files.values_mut() {
+            info.retain_files(|role, file, deleted| {
+                target_filetype.includes_role(role) && filter.matches_file(file, deleted)
+            });
+            dropped |= filter.filters_files() && info.files.is_empty();
+        }
+        if dropped {
+            self.retain(|_, info| !info.files.is_empty());
+        }
+        if !target_filetype.includes_socket() {
+            self.unix_sockets.clear();
+        }
+    }
+
+    /// Only keep the files matching `f`, and the processes left with any.
+    /// `files_to_pid` has to be constructed again
+    pub fn retain_files(&mut self, mut f: impl FnMut(&IStr) -> bool) {
+        for info in self.pid_to_files.values_mut() {
+            info.retain_files(|_, file, _| f(file));
+        }
+        self.files_to_pid = None;
+        self.retain(|_, info| !info.files.is_empty());
+    }
+
+    /// The unix sockets seen during the scan, keyed by inode
+    #[must_use]
+    pub fn unix_sockets(&self) -> &FMap<u64, UnixSocket> {
+        &self.unix_sockets
     }
 
     /// Description.
-    /// Find a certain file in the lsof data and return the Info of the processes
+    /// Find a certain file (or bound unix socket path) in the lsof data and return the Info of the processes
     ///
     /// # Arguments
     ///
@@ -283,8 +644,13 @@ impl Data {
     /// anyhow: path not found
     ///
     /// # Examples
-    /// ```rust
-    /// write me later
+    /// ```no_run
+    /// let mut data = lsof::Data::lsof_all()?;
+    /// data.invert_pid_to_files("");
+    /// for proc in data.find("/dev/null")?.into_iter().flatten() {
+    ///     println!("{} {:?}", proc.pid, proc.name);
+    /// }
+    /// # anyhow::Ok(())
     /// ```
     pub fn find(&self, path: &str) -> Result<Vec<Result<Proc, u64>>> {
         let files_to_pid = self
@@ -293,17 +659,132 @@ impl Data {
         let t = files_to_pid
             .get(path)
             .ok_or_else(|| anyhow!("{path} not found in lsof"))?;
-        let result = t
-            .pids
+        Ok(self.procs(t.pids.iter().copied()))
+    }
+
+    /// Find the processes holding a tcp/udp socket bound to or connected on `port`
+    ///
+    /// # Errors
+    /// anyhow: `files_to_pid` not constructed, or no open socket uses the port
+    pub fn find_port(&self, port: u16) -> Result<Vec<Result<Proc, u64>>> {
+        let sockets = self.proc_root.inet_sockets();
+        self.find_sockets(sockets.iter().filter(|s| s.has_port(port)))
+            .with_context(|| format!("port {port} not found in lsof"))
+    }
+
+    /// Join sockets from `/proc/net` against the `socket:[N]` links of each process
+    ///
+    /// # Errors
+    /// anyhow: `files_to_pid` not constructed, or none of the sockets are open
+    pub fn find_sockets<'a>(
+        &self,
+        sockets: impl IntoIterator<Item = &'a InetSocket>,
+    ) -> Result<Vec<Result<Proc, u64>>> {
+        let files_to_pid = self
+            .files_to_pid()
+            .context("did not construct files_to_pid yet")?;
+        let mut pids = fset(0);
+        for socket in sockets {
+            if let Some(info) = files_to_pid.get(socket.fd_link().as_str()) {
+                pids.extend(&info.pids);
+            }
+        }
+        if pids.is_empty() {
+            bail!("no matching sockets are open");
+        }
+        Ok(self.procs(pids))
+    }
+
+    /// The files in `dir`, or anywhere under it if `recursive`, and `dir` itself, like lsof's `+d` and `+D`.
+    /// Found through a sorted index of `files_to_pid`, built on first use
+    ///
+    /// # Errors
+    /// anyhow: `files_to_pid` not constructed
+    pub fn files_under(&self, dir: &str, recursive: bool) -> Result<Vec<IStr>> {
+        let files_to_pid = self
+            .files_to_pid()
+            .context("did not construct files_to_pid yet")?;
+        let index = self
+            .dir_index
+            .get_or_init(|| files_to_pid.keys().cloned().sorted_unstable().collect());
+        // `/` itself is found as the start of the range
+        let dir = dir.trim_end_matches('/');
+        let prefix = format!("{dir}/");
+        let start = index.partition_point(|file| **file < *prefix);
+        let under = index[start..]
             .iter()
-            .map(|s| {
+            .take_while(|file| file.starts_with(&prefix))
+            .filter(|file| recursive || !file[prefix.len()..].contains('/'));
+        let itself = index
+            .binary_search_by(|file| (**file).cmp(dir))
+            .ok()
+            .map(|i| &index[i]);
+        Ok(chain!(itself, under).cloned().collect())
+    }
+
+    /// The processes using any file under `dir`, see [`Data::files_under`]
+    ///
+    /// # Errors
+    /// anyhow: `files_to_pid` not constructed, or nothing under `dir` is in use
+    pub fn find_under(&self, dir: &str, recursive: bool) -> Result<Vec<Result<Proc, u64>>> {
+        let files = self.files_under(dir, recursive)?;
+        let files_to_pid = self.files_to_pid().context("checked above")?;
+        let mut pids = fset(0);
+        for file in &files {
+            pids.extend(&files_to_pid[file].pids);
+        }
+        if pids.is_empty() {
+            bail!("nothing under {dir} found in lsof");
+        }
+        Ok(self.procs(pids))
+    }
+
+    /// Only keep the files under `dir` and the processes using them, see [`Data::files_under`].
+    /// `files_to_pid` has to be constructed again afterwards
+    ///
+    /// # Panics
+    /// Never, `files_to_pid` is constructed first
+    pub fn retain_under(&mut self, dir: &str, recursive: bool) {
+        self.invert_pid_to_files("");
+        let keep = self
+            .files_under(dir, recursive)
+            .expect("We just constructed files_to_pid")
+            .into_iter()
+            .collect();
+        self.retain_paths(keep);
+    }
+
+    /// Only keep `file` and the processes using it, like [`Data::retain_under`] for a single file.
+    /// `files_to_pid` has to be constructed again afterwards
+    pub fn retain_file(&mut self, file: &str) {
+        let mut keep = fset(1);
+        keep.insert(IStr::from(file));
+        self.retain_paths(keep);
+    }
+
+    /// Only keep the files in `keep`, and the unix sockets bound to a path in `keep`
+    fn retain_paths(&mut self, mut keep: FSet<IStr>) {
+        // Bound unix sockets are found by their path
+        let sockets = self
+            .unix_sockets
+            .values()
+            .filter(|socket| socket.path.as_ref().is_some_and(|path| keep.contains(path)));
+        let sockets = sockets
+            .map(|socket| IStr::from(format!("socket:[{}]", socket.inode)))
+            .collect_vec();
+        keep.extend(sockets);
+        self.retain_files(|file| keep.contains(file));
+    }
+
+    fn procs(&self, pids: impl IntoIterator<Item = u64>) -> Vec<Result<Proc, u64>> {
+        pids.into_iter()
+            .map(|pid| {
                 self.pid_to_files
-                    .get(s)
-                    .map(|p| (*s, p.clone()).into())
-                    .ok_or(*s)
+                    .get(&pid)
+                    .map(|p| (pid, p.clone()).into())
+                    .ok_or(pid)
             })
-            .collect();
-        Ok(result)
+            .collect()
     }
 
     #[must_use]
@@ -312,12 +793,12 @@ impl Data {
     }
 
     #[must_use]
-    pub fn files_to_pid(&self) -> Option<&FMap<String, FdInfo>> {
+    pub fn files_to_pid(&self) -> Option<&FMap<IStr, FdInfo>> {
         self.files_to_pid.as_ref()
     }
 
     #[must_use]
-    pub fn proc_to_files(&self) -> FMap<&'static str, (Vec<u64>, FSet<&'static str>)> {
+    pub fn proc_to_files(&self) -> FMap<IStr, (Vec<u64>, FSet<IStr>)> {
         self.clone().into_proc_to_files()
     }
 
@@ -327,18 +808,18 @@ impl Data {
     }
 
     #[must_use]
-    pub fn into_files_to_pid(mut self) -> FMap<String, FdInfo> {
+    pub fn into_files_to_pid(mut self) -> FMap<IStr, FdInfo> {
         self.invert_pid_to_files("");
         self.files_to_pid.expect("We just constructed it")
     }
 
     #[must_use]
-    pub fn into_proc_to_files(self) -> FMap<&'static str, (Vec<u64>, FSet<&'static str>)> {
+    pub fn into_proc_to_files(self) -> FMap<IStr, (Vec<u64>, FSet<IStr>)> {
         let map = self.into_pid_to_files();
         let mut proc_to_files = fmap(map.len());
-        for (pid, ProcInfo { name, files }) in map {
+        for (pid, ProcInfo { name, files, .. }) in map {
             let (pids, fileset) = proc_to_files
-                .entry(name.unwrap_or_else(|| pid.to_string().leak_str()))
+                .entry(name.unwrap_or_else(|| pid.to_string().into()))
                 .or_insert_with(|| (vec![], fset(files.len())));
             fileset.extend(files);
             pids.push(pid);
@@ -346,109 +827,190 @@ impl Data {
         proc_to_files
     }
     pub fn invert_pid_to_files(&mut self, target_filename: &str) {
+        self.dir_index.take();
         let (pid_to_files, files_to_pid) = self.as_mut();
         for (pid, info) in pid_to_files {
             let files = &info.files;
             if target_filename.is_empty() {
-                file_to_pid_extend(files_to_pid, files.iter().map(|file| (*file, *pid)));
+                file_to_pid_extend(files_to_pid, files.iter().map(|file| (file, *pid)));
             } else {
                 file_to_pid_extend(
                     files_to_pid,
                     files
                         .iter()
-                        .filter(|&&file| target_filename == file)
-                        .map(|file| (*file, *pid)),
+                        .filter(|&file| target_filename == &**file)
+                        .map(|file| (file, *pid)),
                 );
             }
         }
+        // Bound unix sockets can also be found by their path
+        let Data {
+            pid_to_files,
+            files_to_pid,
+            unix_sockets,
+            ..
+        } = self;
+        let files_to_pid = files_to_pid.get_or_insert_with(|| fmap(0));
+        for (pid, info) in pid_to_files {
+            file_to_pid_extend(
+                files_to_pid,
+                info.files
+                    .iter()
+                    .filter_map(|file| unix_socket_path(unix_sockets, file))
+                    .filter(|&path| target_filename.is_empty() || target_filename == &**path)
+                    .map(|path| (path, *pid)),
+            );
+        }
     }
 }
 
+/// A coarse kind of file for grouping: `socket`, `pipe`, `anon_inode`, `dev`,
+/// the pseudo-path for `[heap]` like mappings, otherwise the extension (eg. `.so`) or `<none>`
+#[must_use]
+pub fn file_kind(file: &str) -> &str {
+    if let Some((kind, _)) = file.split_once(":[") {
+        return kind;
+    }
+    if let Some(kind) = file.strip_prefix("anon_inode:").map(|_| "anon_inode") {
+        return kind;
+    }
+    if file.starts_with("/dev/") {
+        return "dev";
+    }
+    if file.starts_with('[') {
+        return file;
+    }
+    let name = file.rsplit_once('/').map_or(file, |(_, name)| name);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => &name[stem.len()..],
+        _ => "<none>",
+    }
+}
+
+fn unix_socket_path<'a>(unix_sockets: &'a FMap<u64, UnixSocket>, file: &str) -> Option<&'a IStr> {
+    unix_sockets.get(&socket_inode(file)?)?.path.as_ref()
+}
+
 fn file_to_pid_extend(
-    files_to_pid: &mut FMap<String, FdInfo>,
-    i: impl IntoIterator<Item = (&str, u64)>,
+    files_to_pid: &mut FMap<IStr, FdInfo>,
+    i: impl IntoIterator<Item = (&IStr, u64)>,
 ) {
     let i = i.into_iter();
     files_to_pid.reserve(i.size_hint().0);
     i.for_each(|(name, pid)| file_to_pid_insert(files_to_pid, name, pid));
 }
 
-fn file_to_pid_insert(files_to_pid: &mut FMap<String, FdInfo>, fname: &str, pid: u64) {
-    #[allow(clippy::enum_glob_use)]
-    use std::collections::hash_map::RawEntryMut::*;
-    // PERF: hashing perf
-    let entry = files_to_pid.raw_entry_mut().from_key(fname);
-    match entry {
-        Occupied(o) => {
-            o.into_mut().pids.insert(pid);
-        }
-        Vacant(v) => {
-            v.insert(
-                fname.to_owned(), // PERF: hotspot
-                FdInfo {
-                    pids: [pid].into_iter().collect(), // PERF: hotspot
-                },
-            );
-        }
+fn file_to_pid_insert(files_to_pid: &mut FMap<IStr, FdInfo>, fname: &IStr, pid: u64) {
+    // PERF: hashing perf, this hashes twice on a miss
+    if let Some(info) = files_to_pid.get_mut(fname) {
+        info.pids.insert(pid);
+    } else {
+        files_to_pid.insert(
+            fname.clone(),
+            FdInfo {
+                pids: [pid].into_iter().collect(), // PERF: hotspot
+            },
+        );
     }
 }
 
-fn extract_pid_from_path(
-    proc_path_r: &std::path::Path,
-) -> (&str, std::result::Result<u64, std::num::ParseIntError>) {
-    // let pid = proc_path_str.split('/').last().unwrap();
-    let Component::Normal(pid) = proc_path_r.components().last().unwrap() else {
-        unreachable!("As constructed this is a normal component");
-    };
-    let pid_str = pid.to_str().unwrap();
-    // This is an assert
-    let pid = pid_str.parse::<u64>();
-    (pid_str, pid)
-}
-
-#[tracing::instrument(level = "trace")]
+#[tracing::instrument(level = "trace", skip(pid_dir, strings))]
 fn get_files_info(
     target_filetype: Filetype,
-    proc_path_str: String,
-) -> (usize, impl Iterator<Item = &'static str> + 'static) {
+    proc_path_str: &str,
+    pid_dir: &DirFd,
+    strings: &Interner,
+) -> (Vec<FileRecord>, Vec<OpenFile>) {
+    let mut buf = [0; libc::PATH_MAX as usize];
+    let links = [
+        (FileRole::Cwd, c"cwd"),
+        (FileRole::Rtd, c"root"),
+        (FileRole::Txt, c"exe"),
+    ]
+    .into_iter()
+    .filter(|&(role, _)| target_filetype.includes_role(role))
+    .filter_map(|(role, link)| {
+        let file = pid_dir.read_link_at(link, &mut buf).ok()?;
+        let (file, deleted) = split_deleted(std::str::from_utf8(file).ok()?);
+        Some(FileRecord {
+            role,
+            file: strings.intern(file),
+            deleted,
+        })
+    });
     let meminfo = target_filetype
         .includes_mem()
-        .then(|| get_mem_info(proc_path_str.clone() + "/maps"))
+        .then(|| get_mem_info(proc_path_str.to_owned(), strings))
         .into_iter()
-        .flatten();
-    // PERF: this glob can just be a read_dir, half the time is spent here
-    let file = glob((proc_path_str + "/fd/*").as_str())
-        .unwrap()
-        .filter_map(std::result::Result::ok)
-        .map(|p| {
-            fs::read_link(&p) // PERF: almost half of the time, do it lazy
-                .unwrap_or(p)
-                .into_os_string()
-                .into_string()
-                .unwrap()
-                .leak_str()
-        }); // PERF: don't clone
-    let cap = meminfo.size_hint().0 + file.size_hint().0;
-    let file = chain!(meminfo, file);
-    (cap, file)
-}
-
-#[tracing::instrument(level = "trace")]
-fn get_mem_info(proc_path_str: String) -> Vec<&'static str> {
-    let path = proc_path_str + "/maps";
-    let Ok(content) = read_to_string(path) else {
+        .flatten()
+        .filter(|r| target_filetype.includes_role(r.role));
+    let records = chain!(links, meminfo).collect();
+    let fds = if target_filetype.includes_fd() {
+        get_fd_info(proc_path_str, pid_dir, strings)
+    } else {
+        Vec::new()
+    };
+    (records, fds)
+}
+
+#[tracing::instrument(level = "trace", skip(pid_dir, strings))]
+fn get_fd_info(proc_path_str: &str, pid_dir: &DirFd, strings: &Interner) -> Vec<OpenFile> {
+    let Ok(fd_dir) = pid_dir.open_dir_at(c"fd") else {
         return Vec::new();
     };
-    content
-        .lines()
-        .filter_map(|line| {
-            let (i, Some(last)) = line
-                .split(' ')
-                .fold((0, None), |(i, _), b| (i + 1, Some(b)))
-            else {
-                return None;
+    let fdinfo_dir = pid_dir.open_dir_at(c"fdinfo").ok();
+    let mut buf = [0; libc::PATH_MAX as usize];
+    let mut name = NumNameBuf::default();
+    fd_dir
+        .numeric_entries()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|fd| {
+            let name = num_name(fd, &mut name);
+            let (file, deleted) = match fd_dir.read_link_at(name, &mut buf) {
+                // PERF: almost half of the time, do it lazy
+                Ok(file) => {
+                    let file = String::from_utf8_lossy(file);
+                    let (file, deleted) = split_deleted(&file);
+                    (strings.intern(file), deleted)
+                }
+                Err(_) => (strings.intern(&format!("{proc_path_str}/fd/{fd}")), false),
             };
-            (i >= 6 && !last.is_empty()).then(|| last.leak_str())
+            let mut open_file = OpenFile {
+                fd: fd.try_into().ok()?,
+                file,
+                deleted,
+                ..OpenFile::default()
+            };
+            if let Some(content) = fdinfo_dir
+                .as_ref()
+                .and_then(|dir| dir.read_to_string_at(name).ok())
+            {
+                parse_fdinfo(&content, &mut open_file);
+            }
+            Some(open_file)
+        })
+        .collect()
+}
+
+#[tracing::instrument(level = "trace", skip(strings))]
+fn get_mem_info(proc_path_str: String, strings: &Interner) -> Vec<FileRecord> {
+    get_pid_maps(proc_path_str)
+        .into_iter()
+        .filter_map(|map| {
+            let role = if map.deleted {
+                FileRole::Del
+            } else {
+                FileRole::Mem
+            };
+            Some((role, map.path?))
+        })
+        // A file is usually mapped several times with different permissions
+        .unique()
+        .map(|(role, file)| FileRecord {
+            role,
+            file: strings.intern(&file),
+            deleted: role == FileRole::Del,
         })
         .collect()
 }
@@ -460,4 +1022,6 @@ fn get_mem_info(proc_path_str: String) -> Vec<&'static str> {
 //     println!("{:?}", result);
 // }
 #[cfg(test)]
+mod fixture;
+#[cfg(test)]
 mod tests;
@@ -1,34 +1,51 @@
 #![warn(clippy::pedantic)]
 #![feature(iter_repeat_n)]
 // REMEMBER: lsof | cut -d " " -f 1 | sort | uniq -c | sort -n -r | head
-use anyhow::{bail, Result};
-use itertools::Itertools;
-use lsof::{buf_stdout, fmap, Data, FMap, Filetype, ProcInfo, StrLeakExt};
+use anyhow::{anyhow, bail, Result};
+use itertools::{chain, Itertools};
+use lsof::{
+    buf_stdout, csv_cell, diff_entries, file_kind, fmap, local_time, terminal_width, tsv_cell,
+    user_column, Change, Column, Data, Entry, FMap, FSet, Fields, Filetype, Filter, IStr,
+    PinnedReport, ProcChange, ProcInfo, RecoverSource, Recovered, Scanner, Table, UserFilter,
+};
+use regex::Regex;
 use tracing::info_span;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::prelude::*;
 
-use colored::Colorize;
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::fs;
 use std::hash::Hash;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::iter::repeat_n;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{cmp::Reverse, io::BufWriter};
+use std::time::{Duration, SystemTime};
+use std::{cmp, thread};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::Colorize;
 
-use clap::{Parser, ValueEnum};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+// Rows are only written as text without serde, `--format json` is rejected in main
+#[cfg(not(feature = "serde"))]
+trait Serialize {}
+#[cfg(not(feature = "serde"))]
+impl<T> Serialize for T {}
 
 #[derive(Parser, Debug)] // requires `derive` feature
-#[command(term_width = 0)] // Just to make testing across clap features easier
+#[command(term_width = 0)]
+#[allow(clippy::struct_excessive_bools)] // Just to make testing across clap features easier
 struct Args {
-    /// Sort the entries of lsof,
-    /// if not given then it is inferred based on `group_by`
-    /// TODO: we should be able to sort by multiple
-    #[arg(short, long)]
-    sort_by: Option<Sorting>,
+    /// Sort the entries of lsof by a comma separated list of keys,
+    /// later keys break ties of earlier ones, prefix a key with `-` to sort it against `--order`.
+    /// If not given then it is inferred based on `group_by`
+    #[arg(short, long, value_delimiter = ',', allow_hyphen_values = true)]
+    sort_by: Vec<SortKey>,
 
     #[arg(short, long, default_value_t)]
     order: Ordering,
@@ -39,25 +56,87 @@ struct Args {
     #[arg(short = 'G', long, default_value_t, requires = "group_by")]
     group_fold: GroupFold,
 
+    /// Number of members listed by `--group-fold sample`
+    #[arg(short = 'n', long, default_value_t = 3)]
+    sample: usize,
+
+    /// Print the total number of files after the text output
     #[arg(short = 'c', long)]
     total_count: bool,
 
+    #[arg(long, default_value_t)]
+    format: Format,
+
+    /// Columns of the table, eg. `command,pid,fd,name`
+    #[arg(long, value_delimiter = ',', value_parser = Column::from_str)]
+    columns: Vec<Column>,
+
+    /// Color the sorted columns, `auto` colors when stdout is a terminal and `NO_COLOR` is unset
+    #[arg(long, default_value_t)]
+    color: ColorChoice,
+
+    /// Output for other programs like lsof's `-F`, eg. `-F pcfn`, all fields if empty.
+    /// Each field is on its own line, `0` ends fields with NUL instead
+    #[arg(short = 'F', long, num_args = 0..=1, default_missing_value = "", value_parser = Fields::from_str, conflicts_with_all = ["group_by", "format"])]
+    fields: Option<Fields>,
+
     /// Exclude listing empty groups
     #[arg(short, long)]
     exclude_empty: bool,
 
-    #[arg(short, long, group = "filter")]
+    #[arg(short, long)]
     file: Option<PathBuf>,
-    #[arg(short = 'F', long, group = "filter")]
-    file_regex: Option<String>,
-    #[arg(short, long, group = "filter")]
+    /// Only list files in this directory and the directory itself, like lsof's `+d`
+    #[arg(long, conflicts_with = "dir_recursive")]
+    dir: Option<PathBuf>,
+    /// Only list files anywhere under this directory, like lsof's `+D`
+    #[arg(long)]
+    dir_recursive: Option<PathBuf>,
+    /// Only list files matching this regex
+    #[arg(long, value_parser = Regex::new)]
+    file_regex: Option<Regex>,
+    /// Only list the files of this pid
+    #[arg(short, long)]
     pid: Option<u64>,
-    #[arg(short = 'P', long, group = "filter")]
-    proc_regex: Option<String>,
+    /// Only list processes whose name matches this regex
+    #[arg(short = 'P', long, value_parser = Regex::new)]
+    proc_regex: Option<Regex>,
 
     #[arg(short = 't', long, default_value = "",  value_parser = Filetype::from_str)]
     filetype: Filetype,
 
+    /// Only list processes of these users, a comma separated list of names or uids,
+    /// prefix with `^` to exclude a user
+    #[arg(short, long, value_parser = UserFilter::from_str)]
+    user: Option<UserFilter>,
+
+    /// Only list files that were deleted while open, like lsof's `+L1`
+    #[arg(long)]
+    deleted: bool,
+    /// Report the space held by deleted files that are still open, per filesystem and per process
+    #[arg(long, conflicts_with_all = ["fields", "group_by"])]
+    pinned: bool,
+
+    /// Read the processes from the procfs mounted here, eg. a container's or the host's at `/host/proc`
+    #[arg(long, default_value = "/proc")]
+    proc_root: String,
+
+    /// Scan again every N seconds, like lsof's `-r N`. With `+N` stop once nothing is listed, like `+r N`.
+    /// Entries that appeared or disappeared since the previous scan are marked `+` or `-` in the table
+    #[arg(short, long, value_parser = Repeat::from_str)]
+    repeat: Option<Repeat>,
+
+    /// Save the scan to this file, for `--load` or `diff` later. With `--repeat` it holds the last scan
+    #[arg(long)]
+    save: Option<PathBuf>,
+    /// List the processes of a snapshot written by `--save` instead of scanning.
+    /// The columns read from the files themselves, like TYPE and SIZE/OFF, are left empty
+    #[arg(long, conflicts_with_all = ["repeat", "pinned"])]
+    load: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(skip)]
     invalidate: PhantomData<Box<()>>,
 
@@ -65,6 +144,20 @@ struct Args {
     bench: usize,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare two snapshots written by `--save`: the processes that started or exited,
+    /// and the files each process opened or closed in between
+    Diff { old: PathBuf, new: PathBuf },
+    /// Copy a deleted file that a process still holds open to DEST, which must not exist yet
+    Recover {
+        /// The path the file had, or `PID:FD` of a descriptor holding it
+        #[arg(value_parser = RecoverSource::from_str)]
+        source: RecoverSource,
+        dest: PathBuf,
+    },
+}
+
 // These should be
 // https://github.com/clap-rs/clap/issues/2621
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, ValueEnum)]
@@ -73,11 +166,20 @@ enum Sorting {
     Pid,
     Filetype,
     ProcName,
+    User,
     NPids,
     NFiles,
     None,
 }
 
+/// One key of `--sort-by`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+struct SortKey {
+    sorting: Sorting,
+    /// Sort this key against `--order`
+    reverse: bool,
+}
+
 #[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Hash, ValueEnum)]
 enum Ordering {
     #[default]
@@ -93,14 +195,55 @@ enum GroupBy {
     Pid,
     Filetype,
     ProcName,
+    User,
+}
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Hash, ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    /// A single array of rows
+    Json,
+    /// One row per line
+    Ndjson,
+    /// Comma separated values with a header
+    Csv,
+    /// Tab separated values with a header
+    Tsv,
+}
+/// `--repeat`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+struct Repeat {
+    interval: Duration,
+    /// Stop once nothing is listed
+    until_empty: bool,
+}
+
+/// The entries of the previous `--repeat` scan, to highlight what changed in the next one
+#[derive(Default, Debug)]
+struct Watch {
+    previous: Option<Vec<Entry>>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Hash, ValueEnum)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 #[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Hash, ValueEnum)]
 enum GroupFold {
     #[default]
     Count,
-    // TODO: List, // Comma separated list of entries
+    /// Comma separated list of the members of each group
+    List,
+    /// Only the first member of each group
+    First,
+    /// The first `--sample` members of each group
+    Sample,
 }
 
+#[allow(clippy::too_many_lines)]
 fn main() -> Result<()> {
     #[cfg(not(feature = "coz"))]
     tracing_subscriber();
@@ -112,23 +255,63 @@ fn main() -> Result<()> {
     let arg_proc_span = info_span!("argument processing");
     let order = args.order;
     let group_by = args.group_by;
-    let sort_by = args.sort_by.unwrap_or(match group_by {
-        GroupBy::None => Sorting::Filename,
-        GroupBy::File => Sorting::NPids,
-        GroupBy::Pid | GroupBy::Filetype | GroupBy::ProcName => Sorting::NFiles,
-    });
+    let mut sort_by = args.sort_by;
+    if sort_by.is_empty() {
+        sort_by.push(SortKey::from(group_by.default_sorting()));
+    }
     let group_fold = args.group_fold;
+    let sample = args.sample;
     let filetypes = args.filetype;
     let filename = args.file.map_or(String::new(), |p| {
         p.into_os_string().into_string().expect("")
     });
-    let lsof_all = filetypes == Filetype::All && filename.is_empty();
+    // Link targets are canonical, unless the directory is in another mount namespace
+    let dir = chain!(
+        args.dir.map(|d| (d, false)),
+        args.dir_recursive.map(|d| (d, true))
+    )
+    .next()
+    .map(|(dir, recursive)| {
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        (dir.to_string_lossy().into_owned(), recursive)
+    });
+    let filter = Filter {
+        pid: args.pid,
+        proc_regex: args.proc_regex,
+        file_regex: args.file_regex,
+        user: args.user,
+        deleted: args.deleted || args.pinned,
+    };
+    let scanner = Scanner::new()
+        .proc_root(&args.proc_root)
+        .filetype(filetypes)
+        .filter(filter.clone());
     let exclude_empty = args.exclude_empty;
     let total_count = args.total_count;
+    let format = args.format;
+    let fields = args.fields;
+    colored::control::set_override(args.color.enabled());
+    let table = Table {
+        columns: if args.columns.is_empty() {
+            Column::DEFAULT.to_vec()
+        } else {
+            args.columns
+        },
+        width: terminal_width(),
+    };
+    if cfg!(not(feature = "serde")) && matches!(format, Format::Json | Format::Ndjson) {
+        bail!("--format {format} needs lsof to be built with the serde feature");
+    }
+    if let Some(command) = args.command {
+        return command.run(&scanner, filetypes, &filter, format);
+    }
     let o = OutputArgs {
         sort_by,
         order,
         group_fold,
+        sample,
+        format,
+        table,
         total_count,
         exclude_empty,
     };
@@ -138,119 +321,671 @@ fn main() -> Result<()> {
     let _invalidate = args.invalidate;
     drop(arg_proc_span);
 
-    for i in 0..args.bench {
-        let lsof = if lsof_all {
-            Data::lsof_all()?
-        } else {
-            let mut data = Data::lsof(filetypes)?;
-            if !filename.is_empty() {
-                data.invert_pid_to_files(&filename);
-            }
-            data
+    let (save, load) = (args.save, args.load);
+    let pinned = args.pinned;
+    let repeat = args.repeat;
+    let mut watch = repeat.map(|_| Watch::default());
+    let iterations = repeat.map_or(args.bench, |_| usize::MAX);
+    for i in 0..iterations {
+        if let (Some(repeat), 1..) = (repeat, i) {
+            thread::sleep(repeat.interval);
+        }
+        let mut lsof = match &load {
+            Some(path) => load_snapshot(path, filetypes, &filter)?,
+            None => scanner.scan()?,
         };
+        if let Some(path) = &save {
+            lsof.save(path)?;
+        }
+        if let Some((dir, recursive)) = &dir {
+            lsof.retain_under(dir, *recursive);
+        }
+        if !filename.is_empty() {
+            // Every output only lists the file, not just `--group-by file`
+            lsof.retain_file(&filename);
+            lsof.invert_pid_to_files(&filename);
+        }
+
+        if exclude_empty {
+            lsof.retain(|_, info| !info.files.is_empty());
+        }
+        let until_empty = repeat.is_some_and(|repeat| repeat.until_empty);
+        if until_empty && lsof.pid_to_files().values().all(|i| i.files.is_empty()) {
+            break;
+        }
 
         let _g = info_span!("output");
+        if pinned {
+            print_pinned(&lsof.pinned(), format)?;
+            continue;
+        }
+        if let Some(fields) = fields {
+            let mut out = buf_stdout(repeat_n((), 1024));
+            lsof.write_fields(fields, &mut out)?;
+            if repeat.is_some() {
+                // lsof's marker between repeats
+                writeln!(out, "m")?;
+            }
+            continue;
+        }
+        if repeat.is_some() && format == Format::Text {
+            // Not println, which panics once the reader is gone
+            let mut out = buf_stdout(repeat_n((), 1));
+            writeln!(out, "======= {}", timestamp())?;
+            out.flush()?;
+        }
         // PERF: all the time is in the printing
-        match group_by {
-            GroupBy::None => output(lsof, o)?,
-            GroupBy::File => group_by_file(lsof, o)?,
-            GroupBy::Pid => group_by_pid(lsof, o)?,
-            GroupBy::Filetype => group_by_filetype(lsof, o)?,
-            GroupBy::ProcName => group_by_proc_name(lsof, o)?,
+        let total = group_by.print(lsof, o.clone(), watch.as_mut())?;
+        if total_count && format == Format::Text {
+            let mut out = buf_stdout(repeat_n((), 1));
+            writeln!(out, "total {total}")?;
+            out.flush()?;
         }
     }
 
     Ok(())
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
 struct OutputArgs {
-    sort_by: Sorting,
+    sort_by: Vec<SortKey>,
     order: Ordering,
     group_fold: GroupFold,
+    sample: usize,
+    format: Format,
+    table: Table,
     total_count: bool,
     exclude_empty: bool,
 }
 
+/// A `--group-by file` row of `--format json`
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct FileRow<'a> {
+    file: &'a str,
+    npids: usize,
+    procs: &'a [&'a str],
+    pids: Vec<u64>,
+}
+/// A `--group-by pid` row of `--format json`
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct PidRow<'a> {
+    pid: u64,
+    proc: Option<&'a str>,
+    user: &'a str,
+    nfiles: usize,
+    files: Vec<&'a str>,
+}
+/// A `--group-by proc-name` or `--group-by user` row of `--format json`
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct NameRow<'a> {
+    name: &'a str,
+    npids: usize,
+    nfiles: usize,
+    pids: &'a [u64],
+}
+/// A `--group-by filetype` row of `--format json`
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct FiletypeRow<'a> {
+    filetype: &'a str,
+    nfiles: usize,
+    npids: usize,
+    files: Vec<&'a str>,
+}
+
+/// A row of the `diff` subcommand in `--format json` or `csv`
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct DiffRow<'a> {
+    pid: u64,
+    proc: Option<&'a str>,
+    /// `started`, `exited` or `running`
+    process: &'static str,
+    opened: Vec<&'a str>,
+    closed: Vec<&'a str>,
+}
+
+/// A row of `--pinned` in `--format json` or `csv`, for a filesystem or for a process
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct PinnedRow<'a> {
+    filesystem: Option<&'a str>,
+    device: Option<String>,
+    pid: Option<u64>,
+    proc: Option<&'a str>,
+    files: usize,
+    bytes: u64,
+}
+
+/// Writes the rows of the output in the `--format`
+struct RowWriter<W: Write> {
+    out: W,
+    format: Format,
+    /// The first line of csv and tsv, even without rows
+    header: Vec<&'static str>,
+    rows: usize,
+}
+
+impl<W: Write> RowWriter<W> {
+    fn new(out: W, format: Format, header: impl IntoIterator<Item = &'static str>) -> Self {
+        RowWriter {
+            out,
+            format,
+            header: header.into_iter().collect(),
+            rows: 0,
+        }
+    }
+    /// `row` is only built for json and csv, `text` writes the line for text output
+    fn row<R: Serialize + Record>(
+        &mut self,
+        row: impl FnOnce() -> R,
+        text: impl FnOnce(&mut W) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        match self.format {
+            Format::Text => {
+                text(&mut self.out)?;
+                self.rows += 1;
+                Ok(())
+            }
+            Format::Json | Format::Ndjson => self.json(&row()),
+            Format::Csv | Format::Tsv => self.record(row().record()),
+        }
+    }
+    #[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+    fn json(&mut self, row: &impl Serialize) -> std::io::Result<()> {
+        #[cfg(feature = "serde")]
+        {
+            let sep = match (self.format, self.rows) {
+                (Format::Json, 0) => "[",
+                (Format::Json, _) => ",",
+                _ => "",
+            };
+            self.out.write_all(sep.as_bytes())?;
+            serde_json::to_writer(&mut self.out, row)?;
+            if self.format == Format::Ndjson {
+                writeln!(self.out)?;
+            }
+            self.rows += 1;
+            Ok(())
+        }
+        #[cfg(not(feature = "serde"))]
+        unreachable!("Rejected in main")
+    }
+    /// Write a line of `--format csv` or `tsv`, after the header for the first one
+    fn record(&mut self, cells: impl IntoIterator<Item = impl AsRef<str>>) -> std::io::Result<()> {
+        if self.rows == 0 {
+            let header = std::mem::take(&mut self.header);
+            self.write_record(header)?;
+        }
+        self.write_record(cells)?;
+        self.rows += 1;
+        Ok(())
+    }
+    fn write_record(
+        &mut self,
+        cells: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> std::io::Result<()> {
+        for (i, cell) in cells.into_iter().enumerate() {
+            let cell = cell.as_ref();
+            let sep = if i == 0 { "" } else { self.format.separator() };
+            let cell = match self.format {
+                Format::Tsv => tsv_cell(cell),
+                _ => csv_cell(cell),
+            };
+            write!(self.out, "{sep}{cell}")?;
+        }
+        writeln!(self.out)
+    }
+    fn finish(mut self) -> std::io::Result<()> {
+        if self.rows == 0 && matches!(self.format, Format::Csv | Format::Tsv) {
+            let header = std::mem::take(&mut self.header);
+            self.write_record(header)?;
+        }
+        if self.format == Format::Json {
+            if self.rows == 0 {
+                write!(self.out, "[")?;
+            }
+            writeln!(self.out, "]")?;
+        }
+        self.out.flush()
+    }
+}
+
+/// A row of `--format csv` or `tsv`
+trait Record {
+    const HEADER: &'static [&'static str];
+    fn record(&self) -> Vec<String>;
+}
+impl Record for FileRow<'_> {
+    const HEADER: &'static [&'static str] = &["file", "npids", "procs", "pids"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.file.to_string(),
+            self.npids.to_string(),
+            self.procs.join(","),
+            self.pids.iter().join(","),
+        ]
+    }
+}
+impl Record for PidRow<'_> {
+    const HEADER: &'static [&'static str] = &["pid", "proc", "user", "nfiles", "files"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.pid.to_string(),
+            self.proc.unwrap_or_default().to_string(),
+            self.user.to_string(),
+            self.nfiles.to_string(),
+            self.files.join(","),
+        ]
+    }
+}
+impl Record for NameRow<'_> {
+    const HEADER: &'static [&'static str] = &["name", "npids", "nfiles", "pids"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.name.to_string(),
+            self.npids.to_string(),
+            self.nfiles.to_string(),
+            self.pids.iter().join(","),
+        ]
+    }
+}
+impl Record for DiffRow<'_> {
+    const HEADER: &'static [&'static str] = &["pid", "proc", "process", "opened", "closed"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.pid.to_string(),
+            self.proc.unwrap_or_default().to_string(),
+            self.process.to_string(),
+            self.opened.join(","),
+            self.closed.join(","),
+        ]
+    }
+}
+impl Record for PinnedRow<'_> {
+    const HEADER: &'static [&'static str] =
+        &["filesystem", "device", "pid", "proc", "files", "bytes"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.filesystem.unwrap_or_default().to_string(),
+            self.device.clone().unwrap_or_default(),
+            self.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+            self.proc.unwrap_or_default().to_string(),
+            self.files.to_string(),
+            self.bytes.to_string(),
+        ]
+    }
+}
+impl Record for FiletypeRow<'_> {
+    const HEADER: &'static [&'static str] = &["filetype", "nfiles", "npids", "files"];
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.filetype.to_string(),
+            self.nfiles.to_string(),
+            self.npids.to_string(),
+            self.files.join(","),
+        ]
+    }
+}
+
 fn tracing_subscriber() {
     tracing_subscriber::registry()
         .with(LevelFilter::INFO)
-        .with(tracing_subscriber::fmt::layer().with_span_events(FmtSpan::ACTIVE))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_span_events(FmtSpan::ACTIVE)
+                .with_writer(std::io::stderr),
+        )
         // .with(TracingCozBridge::new())
         .init();
 }
 
-#[tracing::instrument(skip(lsof), level = "info")]
-fn output(lsof: Data, OutputArgs { sort_by, order, .. }: OutputArgs) -> Result<()> {
-    match sort_by {
-        Sorting::NPids => bail!("Sorting by npids not implemented"),
-        Sorting::NFiles => bail!("Sorting by nfiles not implemented"),
-        _ => {}
-    }
+/// Local time for the `--repeat` separator, eg. `2024-06-01 12:30:00`
+fn timestamp() -> String {
+    let tm = local_time(SystemTime::now());
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+#[tracing::instrument(skip(lsof, watch), level = "info")]
+fn output(
+    lsof: Data,
+    OutputArgs {
+        sort_by,
+        order,
+        format,
+        table,
+        ..
+    }: OutputArgs,
+    watch: Option<&mut Watch>,
+) -> Result<usize> {
+    let sorts_by = |sorting| sort_by.iter().any(|key| key.sorting == sorting);
+    let nfiles: FMap<u64, usize> = if sorts_by(Sorting::NFiles) {
+        let map = lsof.pid_to_files().iter();
+        map.map(|(pid, info)| (*pid, info.files.len())).collect()
+    } else {
+        fmap(0)
+    };
     let mut all = lsof.flattened().collect_vec();
-    match (sort_by, order) {
-        (Sorting::Filename, Ordering::Ascending) => all.sort_unstable_by_key(|e| e.file),
-        (Sorting::Filename, Ordering::Descending) => all.sort_unstable_by_key(|e| Reverse(e.file)),
-        (Sorting::Pid, Ordering::Ascending) => all.sort_unstable_by_key(|e| e.pid),
-        (Sorting::Pid, Ordering::Descending) => all.sort_unstable_by_key(|e| Reverse(e.pid)),
-        (Sorting::Filetype, Ordering::Ascending) => {
-            all.sort_unstable_by_key(|e| e.get_ext());
-        }
-        (Sorting::Filetype, Ordering::Descending) => {
-            all.sort_unstable_by_key(|e| Reverse(e.get_ext()));
-        }
-        (Sorting::ProcName, Ordering::Ascending) => all.sort_unstable_by_key(|e| e.proc),
-        (Sorting::ProcName, Ordering::Descending) => all.sort_unstable_by_key(|e| Reverse(e.proc)),
-        (Sorting::NPids | Sorting::NFiles, _) => {
-            unreachable!("Handled above because we need to group")
-        }
-        (Sorting::None, _) => {}
-    }
-    let mut stdout = buf_stdout(all.iter());
-    for lsof::Entry { pid, proc, file } in all {
-        // TODO: prettify
-        let file = if sort_by == Sorting::Filename {
-            file.bold()
-        } else {
-            file.into()
+    let npids = if sorts_by(Sorting::NPids) {
+        let holders = all.iter().map(|e| (e.file.clone(), e.pid)).unique();
+        holders.map(|(file, _)| file).counts()
+    } else {
+        HashMap::new()
+    };
+    all.sort_unstable_by(comparator(
+        &sort_by,
+        |a: &Entry, b, sorting| match sorting {
+            Sorting::Filename => a.file.cmp(&b.file),
+            Sorting::Pid => a.pid.cmp(&b.pid),
+            Sorting::Filetype => a.get_ext().cmp(b.get_ext()),
+            Sorting::ProcName => a.proc.cmp(&b.proc),
+            Sorting::User => a.user.cmp(&b.user),
+            Sorting::NPids => npids.get(&a.file).cmp(&npids.get(&b.file)),
+            Sorting::NFiles => nfiles.get(&a.pid).cmp(&nfiles.get(&b.pid)),
+            Sorting::None => cmp::Ordering::Equal,
+        },
+    ));
+    if order == Ordering::Descending {
+        all.reverse();
+    }
+    let total = all.len();
+    if format == Format::Text {
+        let bold = |column| match column {
+            Column::Command => sorts_by(Sorting::ProcName),
+            Column::Pid => sorts_by(Sorting::Pid),
+            Column::User => sorts_by(Sorting::User),
+            Column::Name => sorts_by(Sorting::Filename),
+            _ => false,
         };
-        let proc = if sort_by == Sorting::ProcName {
-            proc.bold()
-        } else {
-            proc.into()
+        let mut out = buf_stdout(all.iter());
+        let Some(watch) = watch else {
+            table.write(&all, bold, &mut out)?;
+            return Ok(total);
         };
-        let pid = if sort_by == Sorting::Pid {
-            pid.to_string().bold()
-        } else {
-            pid.to_string().into()
+        let changes = watch
+            .previous
+            .as_deref()
+            .map(|previous| diff_entries(previous, &all))
+            .unwrap_or_default();
+        let added: FSet<_> = changes
+            .iter()
+            .filter(|&&(change, _)| change == Change::Added)
+            .map(|(_, entry)| entry.key())
+            .collect();
+        let rows = all.iter().map(|entry| {
+            let change = added.contains(&entry.key()).then_some(Change::Added);
+            (entry, change)
+        });
+        let removed = changes
+            .iter()
+            .filter(|&&(change, _)| change == Change::Removed)
+            .map(|&(change, entry)| (entry, Some(change)));
+        table.write_changes(rows.chain(removed), bold, &mut out)?;
+        watch.previous = Some(all);
+        return Ok(total);
+    }
+    let header = table.columns.iter().map(|c| c.header());
+    let mut stdout = RowWriter::new(buf_stdout(all.iter()), format, header);
+    for entry in &all {
+        match format {
+            Format::Text => unreachable!("Written as a table above"),
+            Format::Json | Format::Ndjson => stdout.json(entry)?,
+            Format::Csv | Format::Tsv => stdout.record(table.cells(entry))?,
+        }
+    }
+    stdout.finish()?;
+
+    Ok(total)
+}
+
+impl Command {
+    fn run(
+        self,
+        scanner: &Scanner,
+        filetype: Filetype,
+        filter: &Filter,
+        format: Format,
+    ) -> Result<()> {
+        match self {
+            Command::Diff { old, new } => {
+                let mut old = load_snapshot(&old, filetype, filter)?;
+                let mut new = load_snapshot(&new, filetype, filter)?;
+                old.invert_pid_to_files("");
+                new.invert_pid_to_files("");
+                print_diff(&old.diff(&new)?, format)
+            }
+            Command::Recover { source, dest } => {
+                let pid = match source {
+                    RecoverSource::Fd { pid, .. } => Some(pid),
+                    RecoverSource::Path(_) => None,
+                };
+                let data = scanner
+                    .clone()
+                    .filetype(Filetype::All)
+                    .filter(Filter {
+                        pid,
+                        // Only the pid for `PID:FD`, to tell why it can't be recovered
+                        deleted: pid.is_none(),
+                        ..Filter::default()
+                    })
+                    .scan()?;
+                let Recovered { from, len } = data.recover(&source, &dest)?;
+                println!(
+                    "recovered {} from {}:{} to {}, {len} bytes",
+                    from.file,
+                    from.pid,
+                    from.role,
+                    dest.display()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Print the `--pinned` report, filesystems then processes
+fn print_pinned(report: &PinnedReport, format: Format) -> Result<()> {
+    let mounts = report
+        .filesystems
+        .iter()
+        .map(|fs| fs.mount_point.as_deref().unwrap_or("?"))
+        .collect_vec();
+    let names = report
+        .procs
+        .iter()
+        .map(|p| p.name.as_deref().unwrap_or("<noname>"))
+        .collect_vec();
+    if format == Format::Text {
+        let width = |header: &str, cells: &[&str]| {
+            cells.iter().map(|c| c.len()).chain([header.len()]).max()
+        };
+        let w = width("FILESYSTEM", &mounts).unwrap_or_default();
+        let mut out = buf_stdout(report.files.iter());
+        writeln!(
+            out,
+            "{:w$} {:>9} {:>5} {:>12}",
+            "FILESYSTEM", "DEVICE", "FILES", "BYTES"
+        )?;
+        for (fs, mount) in report.filesystems.iter().zip(&mounts) {
+            let dev = format!("{},{}", fs.dev.0, fs.dev.1);
+            writeln!(out, "{mount:w$} {dev:>9} {:>5} {:>12}", fs.files, fs.bytes)?;
+        }
+        let w = width("COMMAND", &names).unwrap_or_default();
+        writeln!(
+            out,
+            "{:>7} {:w$} {:>5} {:>12}",
+            "PID", "COMMAND", "FILES", "BYTES"
+        )?;
+        for (proc, name) in report.procs.iter().zip(&names) {
+            writeln!(
+                out,
+                "{:>7} {name:w$} {:>5} {:>12}",
+                proc.pid, proc.files, proc.bytes
+            )?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    let mut stdout = RowWriter::new(
+        buf_stdout(report.files.iter()),
+        format,
+        PinnedRow::HEADER.iter().copied(),
+    );
+    for (fs, mount) in report.filesystems.iter().zip(&mounts) {
+        let row = || PinnedRow {
+            filesystem: Some(mount),
+            device: Some(format!("{},{}", fs.dev.0, fs.dev.1)),
+            pid: None,
+            proc: None,
+            files: fs.files,
+            bytes: fs.bytes,
+        };
+        stdout.row(row, |_| unreachable!("Written above for text"))?;
+    }
+    for (proc, name) in report.procs.iter().zip(&names) {
+        let row = || PinnedRow {
+            filesystem: None,
+            device: None,
+            pid: Some(proc.pid),
+            proc: Some(name),
+            files: proc.files,
+            bytes: proc.bytes,
         };
-        writeln!(stdout, "{pid} {proc} {file}")?;
+        stdout.row(row, |_| unreachable!("Written above for text"))?;
     }
+    stdout.finish()?;
+    Ok(())
+}
+
+/// A snapshot written by `--save`, with the filters of the command line applied
+fn load_snapshot(path: &Path, filetype: Filetype, filter: &Filter) -> Result<Data> {
+    let mut data = Data::load(path)?;
+    data.retain_matching(filetype, filter);
+    Ok(data)
+}
 
+/// Print the output of the `diff` subcommand
+fn print_diff(changes: &[ProcChange], format: Format) -> Result<()> {
+    let mut stdout = RowWriter::new(
+        buf_stdout(changes.iter()),
+        format,
+        DiffRow::HEADER.iter().copied(),
+    );
+    for c in changes {
+        let row = || DiffRow {
+            pid: c.pid,
+            proc: c.name.as_deref(),
+            process: match c.change {
+                Some(Change::Added) => "started",
+                Some(Change::Removed) => "exited",
+                None => "running",
+            },
+            opened: c.opened.iter().map(|f| &**f).collect(),
+            closed: c.closed.iter().map(|f| &**f).collect(),
+        };
+        stdout.row(row, |out| {
+            let name = c.name.as_deref().unwrap_or("<noname>");
+            match c.change {
+                Some(Change::Added) => writeln!(out, "{} {name} started", c.pid)?,
+                Some(Change::Removed) => writeln!(out, "{} {name} exited", c.pid)?,
+                None => writeln!(out, "{} {name}", c.pid)?,
+            }
+            for file in &c.opened {
+                writeln!(out, "  {}", format!("+ {file}").green())?;
+            }
+            for file in &c.closed {
+                writeln!(out, "  {}", format!("- {file}").red())?;
+            }
+            Ok(())
+        })?;
+    }
+    stdout.finish()?;
     Ok(())
 }
 
-#[tracing::instrument(skip(lsof), level = "info")]
+// #[tracing::instrument(skip(lsof), level = "info")]
 fn group_by_file(
-    lsof: Data,
+    mut lsof: Data,
     OutputArgs {
         sort_by,
         order,
         group_fold,
-        total_count,
-        exclude_empty,
+        sample,
+        format,
+        ..
     }: OutputArgs,
-) -> Result<()> {
-    let map = lsof.files_to_pid();
-    match sort_by {
-        Sorting::Filename => todo!(),
-        Sorting::Pid => bail!("Can't sort by pid when grouping by file (the pids are folded)"),
-        Sorting::Filetype => todo!(),
-        Sorting::ProcName => todo!(),
-        Sorting::NPids => todo!(),
-        Sorting::NFiles => bail!("Can't sort by # of files when grouping by file (its 1)"),
-        Sorting::None => todo!(),
+) -> Result<usize> {
+    for key in &sort_by {
+        match key.sorting {
+            Sorting::Pid => bail!("Can't sort by pid when grouping by file (the pids are folded)"),
+            Sorting::User => {
+                bail!("Can't sort by user when grouping by file (the users are folded)")
+            }
+            Sorting::NFiles => bail!("Can't sort by # of files when grouping by file (its 1)"),
+            _ => {}
+        }
+    }
+    if lsof.files_to_pid().is_none() {
+        lsof.invert_pid_to_files("");
     }
+    let pid_to_files = lsof.pid_to_files();
+    let rows = lsof
+        .files_to_pid()
+        .expect("We just constructed it")
+        .iter()
+        .map(|(file, info)| {
+            let holders = info
+                .pids
+                .iter()
+                .sorted_unstable()
+                .filter_map(|pid| {
+                    pid_to_files
+                        .get(pid)
+                        .map(|info| (info.name.as_deref(), *pid))
+                })
+                .map(|(name, pid)| (name.unwrap_or("<noname>"), pid))
+                .collect_vec();
+            let names = holders
+                .iter()
+                .map(|&(name, _)| name)
+                .sorted_unstable()
+                .dedup()
+                .collect_vec();
+            (&**file, info.pids.len(), names, holders)
+        });
+    let rows = sort_rows(rows, &sort_by, |a, b, sorting| match sorting {
+        Sorting::Filename => a.0.cmp(b.0),
+        Sorting::Filetype => (file_kind(a.0), a.0).cmp(&(file_kind(b.0), b.0)),
+        Sorting::ProcName => a.2.cmp(&b.2),
+        Sorting::NPids => (a.1, a.0).cmp(&(b.1, b.0)),
+        _ => cmp::Ordering::Equal,
+    });
+    let header = FileRow::HEADER.iter().copied();
+    let mut stdout = RowWriter::new(buf_stdout(repeat_n((), 1024)), format, header);
+    let mut total = 0;
+    print_map(order, rows, |(file, npids, names, holders)| {
+        total += npids;
+        let row = || FileRow {
+            file,
+            npids,
+            procs: &names,
+            pids: holders.iter().map(|&(_, pid)| pid).collect(),
+        };
+        stdout.row(row, |out| match group_fold {
+            GroupFold::Count => writeln!(out, "{file} {npids} {names}", names = names.join(",")),
+            fold => {
+                let holders = holders.iter().map(|(name, pid)| format!("{name}({pid})"));
+                writeln!(out, "{file} {}", fold.members(holders, sample))
+            }
+        })
+    })?;
+    stdout.finish()?;
+    Ok(total)
 }
 
 // #[tracing::instrument(skip(lsof), level = "info")]
@@ -260,125 +995,148 @@ fn group_by_pid(
         sort_by,
         order,
         group_fold,
-        total_count,
-        exclude_empty,
+        sample,
+        format,
+        ..
     }: OutputArgs,
-) -> Result<()> {
+) -> Result<usize> {
+    for key in &sort_by {
+        match key.sorting {
+            Sorting::Filename => {
+                bail!("Can't sort by filename when grouping by pid (the filenames are folded)")
+            }
+            Sorting::NPids => bail!("Can't sort by # of pids when grouping by pid (its 1)"),
+            Sorting::Filetype => {
+                bail!("Can't sort by filetype when grouping by pid (the files are folded)")
+            }
+            _ => {}
+        }
+    }
     let map = lsof.into_pid_to_files();
-    let map = fold_by_pid_w_count(map, |_, info| info.name);
-    let mut stdout = buf_stdout(repeat_n((), 1024));
-    let map = match sort_by {
-        Sorting::Filename => {
-            bail!("Can't sort by filename when grouping by pid (the filenames are folded)")
-        }
-        Sorting::NPids => bail!("Can't sort by # of pids when grouping by pid (its 1)"),
-        Sorting::Filetype => {
-            bail!("Can't sort by filetype when grouping by pid (the files are folded)")
-        }
-        Sorting::Pid => match group_fold {
-            GroupFold::Count => map.sorted_unstable_by_key(|(pid, _, _)| *pid),
-        },
-        Sorting::ProcName => match group_fold {
-            GroupFold::Count => map.sorted_unstable_by_key(|&(_, pname, _)| pname),
-        },
-        Sorting::NFiles => match group_fold {
-            GroupFold::Count => map.sorted_unstable_by_key(|(_, _, nfiles)| *nfiles),
-        },
-        Sorting::None => match group_fold {
-            GroupFold::Count => {
-                for (pid, pname, nfiles) in map {
-                    let pname = pname.unwrap_or("<noname>");
-                    writeln!(stdout, "{pid} {pname} {nfiles}")?;
-                }
-                return Ok(());
+    let total = count_files(&map);
+    let map = fold_by_pid_w_count(map, |_, info| {
+        (info.name, user_column(info.uid), info.files)
+    });
+    let map = sort_rows(map, &sort_by, |a, b, sorting| match sorting {
+        Sorting::Pid => a.0.cmp(&b.0),
+        Sorting::ProcName => a.1 .0.cmp(&b.1 .0),
+        Sorting::User => a.1 .1.cmp(&b.1 .1),
+        Sorting::NFiles => a.2.cmp(&b.2),
+        _ => cmp::Ordering::Equal,
+    });
+    let header = PidRow::HEADER.iter().copied();
+    let mut stdout = RowWriter::new(buf_stdout(repeat_n((), 1024)), format, header);
+    print_map(order, map, |(pid, (pname, user, files), nfiles)| {
+        let row = || PidRow {
+            pid,
+            proc: pname.as_deref(),
+            user: &user,
+            nfiles,
+            files: files.iter().map(|file| &**file).sorted_unstable().collect(),
+        };
+        let pname = pname.as_deref().unwrap_or("<noname>");
+        stdout.row(row, |out| match group_fold {
+            GroupFold::Count => writeln!(out, "{pid} {pname} {user} {nfiles}"),
+            fold => {
+                let files = fold.members(files.iter().sorted_unstable(), sample);
+                writeln!(out, "{pid} {pname} {user} {files}")
             }
-        },
-    };
-    print_map(order, map, |(pid, pname, nfiles)| {
-        let pname = pname.unwrap_or("<noname>");
-        writeln!(stdout, "{pid} {pname} {nfiles}")
+        })
     })?;
-    Ok(())
+    stdout.finish()?;
+    Ok(total)
 }
 
 // #[tracing::instrument(skip(lsof), level = "info")]
-fn group_by_proc_name(
-    lsof: Data,
+fn group_by_proc_name(lsof: Data, o: OutputArgs) -> Result<usize> {
+    for key in &o.sort_by {
+        match key.sorting {
+            Sorting::Filename => {
+                bail!("Can't sort by filename when grouping by process name (the filenames are folded)")
+            }
+            Sorting::Filetype => {
+                bail!("Can't sort by filetype when grouping by process name (the files are folded)")
+            }
+            Sorting::User => {
+                bail!("Can't sort by user when grouping by process name (the users are folded)")
+            }
+            _ => {}
+        }
+    }
+    let map = lsof.into_pid_to_files();
+    let total = count_files(&map);
+    let capacity = map.len();
+    let map = fold_by_proc_name_w_pids(map, capacity);
+    print_pid_groups(map, Sorting::ProcName, o)?;
+    Ok(total)
+}
+
+// #[tracing::instrument(skip(lsof), level = "info")]
+fn group_by_user(lsof: Data, o: OutputArgs) -> Result<usize> {
+    for key in &o.sort_by {
+        match key.sorting {
+            Sorting::Filename => {
+                bail!("Can't sort by filename when grouping by user (the filenames are folded)")
+            }
+            Sorting::Filetype => {
+                bail!("Can't sort by filetype when grouping by user (the files are folded)")
+            }
+            Sorting::ProcName => {
+                bail!("Can't sort by proc name when grouping by user (the procs are folded)")
+            }
+            _ => {}
+        }
+    }
+    let map = lsof.into_pid_to_files();
+    let total = count_files(&map);
+    let capacity = map.len();
+    let map = fold_by_user_w_pids(map, capacity);
+    print_pid_groups(map, Sorting::User, o)?;
+    Ok(total)
+}
+
+/// Print groups of pids keyed by a name, `name_key` is the `Sorting` of that name
+fn print_pid_groups(
+    map: FMap<IStr, (Vec<u64>, usize)>,
+    name_key: Sorting,
     OutputArgs {
         sort_by,
         order,
         group_fold,
-        total_count,
-        exclude_empty,
+        sample,
+        format,
+        ..
     }: OutputArgs,
 ) -> Result<()> {
-    let map = lsof.into_pid_to_files();
-    let capacity = map.len();
-    let mut stdout = buf_stdout(repeat_n((), 1024));
-
-    match sort_by {
-        Sorting::Filename => {
-            bail!("Can't sort by filename when grouping by pid (the filenames are folded)")
-        }
-        Sorting::Filetype => {
-            bail!("Can't sort by filetype when grouping by pid (the files are folded)")
-        }
-        Sorting::Pid => match group_fold {
-            GroupFold::Count => {
-                let map = fold_by_proc_name_w_count(
-                    map,
-                    capacity,
-                    |pid, _| pid,
-                    |minpid, pid, _| minpid.min(pid),
-                );
-                let map = map.into_iter().sorted_unstable_by_key(|(_, (pid, _))| *pid);
-                print_map(order, map, |(pname, (pid, nfiles))| {
-                    writeln!(stdout, "{pname} {pid} {nfiles}")
-                })?;
-            }
-        },
-        Sorting::ProcName => match group_fold {
-            GroupFold::Count => {
-                let map = fold_by_proc_name_w_count(map, capacity, |_, _| (), |(), _, _| ());
-                let map = map.into_iter().sorted_unstable_by_key(|&(pname, _)| pname);
-                print_map(order, map, |(pname, ((), nfiles))| {
-                    writeln!(stdout, "{pname} {nfiles}")
-                })?;
-            }
-        },
-        Sorting::NPids => match group_fold {
-            GroupFold::Count => {
-                let map = fold_by_proc_name_w_count(map, capacity, |_, _| 1, |i, _, _| i + 1);
-                let map = map
-                    .into_iter()
-                    .sorted_unstable_by_key(|&(_, (pids, nfiles))| (pids, nfiles));
-                print_map(order, map, |(pname, (pids, nfiles))| {
-                    writeln!(stdout, "{pname} {pids} {nfiles}")
-                })?;
-            }
-        },
-        Sorting::NFiles => match group_fold {
-            GroupFold::Count => {
-                let map = fold_by_proc_name_w_count(map, capacity, |_, _| (), |(), _, _| ());
-                let map = map
-                    .into_iter()
-                    .sorted_unstable_by_key(|(_, nfiles)| *nfiles);
-                print_map(order, map, |(pname, ((), nfiles))| {
-                    writeln!(stdout, "{pname} {nfiles}")
-                })?;
-            }
-        },
-        Sorting::None => match group_fold {
-            GroupFold::Count => {
-                let map = fold_by_proc_name_w_count(map, capacity, |_, _| (), |(), _, _| ());
-                for (pname, ((), nfiles)) in map {
-                    writeln!(stdout, "{pname} {nfiles}")?;
-                }
-            }
+    let map = sort_rows(
+        map,
+        &sort_by,
+        |(a, (apids, an)), (b, (bpids, bn)), sorting| match sorting {
+            Sorting::Pid => apids[0].cmp(&bpids[0]),
+            Sorting::NPids => (apids.len(), an).cmp(&(bpids.len(), bn)),
+            Sorting::NFiles => an.cmp(bn),
+            _ if sorting == name_key => a.cmp(b),
+            _ => cmp::Ordering::Equal,
         },
-    };
-
-    Ok(())
+    );
+    let header = NameRow::HEADER.iter().copied();
+    let mut stdout = RowWriter::new(buf_stdout(repeat_n((), 1024)), format, header);
+    let first_key = sort_by.first().map_or(Sorting::None, |key| key.sorting);
+    print_map(order, map, |(name, (pids, nfiles))| {
+        let row = || NameRow {
+            name: &name,
+            npids: pids.len(),
+            nfiles,
+            pids: &pids,
+        };
+        stdout.row(row, |out| match (group_fold, first_key) {
+            (GroupFold::Count, Sorting::Pid) => writeln!(out, "{name} {} {nfiles}", pids[0]),
+            (GroupFold::Count, Sorting::NPids) => writeln!(out, "{name} {} {nfiles}", pids.len()),
+            (GroupFold::Count, _) => writeln!(out, "{name} {nfiles}"),
+            (fold, _) => writeln!(out, "{name} {}", fold.members(&pids, sample)),
+        })
+    })?;
+    Ok(stdout.finish()?)
 }
 
 #[tracing::instrument(skip(lsof), level = "info")]
@@ -388,11 +1146,69 @@ fn group_by_filetype(
         sort_by,
         order,
         group_fold,
-        total_count,
-        exclude_empty,
+        sample,
+        format,
+        ..
     }: OutputArgs,
-) -> Result<()> {
-    todo!()
+) -> Result<usize> {
+    for key in &sort_by {
+        match key.sorting {
+            Sorting::Filename => {
+                bail!("Can't sort by filename when grouping by filetype (the filenames are folded)")
+            }
+            Sorting::Pid => {
+                bail!("Can't sort by pid when grouping by filetype (the pids are folded)")
+            }
+            Sorting::ProcName => {
+                bail!("Can't sort by proc name when grouping by filetype (the procs are folded)")
+            }
+            Sorting::User => {
+                bail!("Can't sort by user when grouping by filetype (the users are folded)")
+            }
+            _ => {}
+        }
+    }
+    let mut kinds: FMap<&str, (usize, FSet<&str>, FSet<u64>)> = fmap(64);
+    for (pid, info) in lsof.pid_to_files() {
+        for file in &info.files {
+            let (nfiles, files, pids) = kinds.entry(file_kind(file)).or_default();
+            *nfiles += 1;
+            if group_fold != GroupFold::Count || format != Format::Text {
+                files.insert(&**file);
+            }
+            pids.insert(*pid);
+        }
+    }
+    let rows = kinds
+        .into_iter()
+        .map(|(kind, (nfiles, files, pids))| (kind, nfiles, pids.len(), files));
+    let rows = sort_rows(rows, &sort_by, |a, b, sorting| match sorting {
+        Sorting::Filetype => a.0.cmp(b.0),
+        Sorting::NFiles => (a.1, a.0).cmp(&(b.1, b.0)),
+        Sorting::NPids => (a.2, a.0).cmp(&(b.2, b.0)),
+        _ => cmp::Ordering::Equal,
+    });
+    let header = FiletypeRow::HEADER.iter().copied();
+    let mut stdout = RowWriter::new(buf_stdout(repeat_n((), 1024)), format, header);
+    let mut total = 0;
+    print_map(order, rows, |(kind, nfiles, npids, files)| {
+        total += nfiles;
+        let row = || FiletypeRow {
+            filetype: kind,
+            nfiles,
+            npids,
+            files: files.iter().copied().sorted_unstable().collect(),
+        };
+        stdout.row(row, |out| match group_fold {
+            GroupFold::Count => writeln!(out, "{kind} {nfiles} {npids}"),
+            fold => {
+                let files = fold.members(files.iter().sorted_unstable(), sample);
+                writeln!(out, "{kind} {files}")
+            }
+        })
+    })?;
+    stdout.finish()?;
+    Ok(total)
 }
 
 impl Display for Sorting {
@@ -400,37 +1216,177 @@ impl Display for Sorting {
         write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
     }
 }
+impl From<Sorting> for SortKey {
+    fn from(sorting: Sorting) -> Self {
+        SortKey {
+            sorting,
+            reverse: false,
+        }
+    }
+}
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (reverse, key) = match s.strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, s),
+        };
+        let sorting = Sorting::from_str(key, true).map_err(|e| anyhow!(e))?;
+        Ok(SortKey { sorting, reverse })
+    }
+}
 impl Display for GroupBy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
     }
 }
+impl GroupBy {
+    /// The `--sort-by` when none is given
+    const fn default_sorting(self) -> Sorting {
+        match self {
+            GroupBy::None => Sorting::Filename,
+            GroupBy::File => Sorting::NPids,
+            GroupBy::Pid | GroupBy::Filetype | GroupBy::ProcName | GroupBy::User => Sorting::NFiles,
+        }
+    }
+    /// Print `lsof` grouped this way, returns the number of files
+    fn print(self, lsof: Data, o: OutputArgs, watch: Option<&mut Watch>) -> Result<usize> {
+        match self {
+            GroupBy::None => output(lsof, o, watch),
+            GroupBy::File => group_by_file(lsof, o),
+            GroupBy::Pid => group_by_pid(lsof, o),
+            GroupBy::Filetype => group_by_filetype(lsof, o),
+            GroupBy::ProcName => group_by_proc_name(lsof, o),
+            GroupBy::User => group_by_user(lsof, o),
+        }
+    }
+}
 impl Display for Ordering {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
     }
 }
+impl GroupFold {
+    /// Join the members of a group, all of them unless only a sample is wanted
+    fn members<T: Display>(self, members: impl IntoIterator<Item = T>, sample: usize) -> String {
+        let n = match self {
+            GroupFold::Count | GroupFold::List => usize::MAX,
+            GroupFold::First => 1,
+            GroupFold::Sample => sample,
+        };
+        members.into_iter().take(n).join(",")
+    }
+}
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
+    }
+}
+impl Format {
+    const fn separator(self) -> &'static str {
+        match self {
+            Format::Tsv => "\t",
+            _ => ",",
+        }
+    }
+}
+impl FromStr for Repeat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (until_empty, secs) = match s.strip_prefix('+') {
+            Some(secs) => (true, secs),
+            None => (false, s),
+        };
+        let secs = secs
+            .parse()
+            .map_err(|_| anyhow!("bad repeat interval {s}, expected N or +N seconds"))?;
+        Ok(Repeat {
+            interval: Duration::from_secs(secs),
+            until_empty,
+        })
+    }
+}
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|c| c.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+impl Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
+    }
+}
 impl Display for GroupFold {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{n}", n = self.to_possible_value().unwrap().get_name())
     }
 }
 
-fn fold_by_proc_name_w_count<T: Copy>(
+fn fold_by_proc_name_w_count<T>(
     map: impl IntoIterator<Item = (u64, ProcInfo)>,
     capacity: usize,
     init: impl Fn(u64, ProcInfo) -> T,
-    fold: impl Fn(T, u64, ProcInfo) -> T,
-) -> FMap<&'static str, (T, usize)> {
+    fold: impl Fn(&mut T, u64, ProcInfo),
+) -> FMap<IStr, (T, usize)> {
     fold_pid_to_files_w_count(
         map,
-        |pid, info| info.name.unwrap_or_else(|| pid.to_string().leak_str()),
+        |pid, info| info.name.clone().unwrap_or_else(|| pid.to_string().into()),
         capacity,
         init,
         fold,
     )
 }
-fn fold_by_pid_w_count<T: Copy>(
+fn fold_by_proc_name_w_pids(
+    map: impl IntoIterator<Item = (u64, ProcInfo)>,
+    capacity: usize,
+) -> FMap<IStr, (Vec<u64>, usize)> {
+    let mut map = fold_by_proc_name_w_count(
+        map,
+        capacity,
+        |pid, _| vec![pid],
+        |pids, pid, _| pids.push(pid),
+    );
+    for (pids, _) in map.values_mut() {
+        pids.sort_unstable();
+    }
+    map
+}
+fn count_files(map: &FMap<u64, ProcInfo>) -> usize {
+    map.values().map(|info| info.files.len()).sum()
+}
+fn fold_by_user_w_count<T>(
+    map: impl IntoIterator<Item = (u64, ProcInfo)>,
+    capacity: usize,
+    init: impl Fn(u64, ProcInfo) -> T,
+    fold: impl Fn(&mut T, u64, ProcInfo),
+) -> FMap<IStr, (T, usize)> {
+    fold_pid_to_files_w_count(map, |_, info| user_column(info.uid), capacity, init, fold)
+}
+fn fold_by_user_w_pids(
+    map: impl IntoIterator<Item = (u64, ProcInfo)>,
+    capacity: usize,
+) -> FMap<IStr, (Vec<u64>, usize)> {
+    let mut map = fold_by_user_w_count(
+        map,
+        capacity,
+        |pid, _| vec![pid],
+        |pids, pid, _| pids.push(pid),
+    );
+    for (pids, _) in map.values_mut() {
+        pids.sort_unstable();
+    }
+    map
+}
+fn fold_by_pid_w_count<T>(
     map: impl IntoIterator<Item = (u64, ProcInfo)>,
     f: impl Fn(u64, ProcInfo) -> T,
 ) -> impl Iterator<Item = (u64, T, usize)> {
@@ -439,12 +1395,12 @@ fn fold_by_pid_w_count<T: Copy>(
         (pid, f(pid, info), len)
     })
 }
-fn fold_pid_to_files_w_count<T: Copy, K: Eq + Hash>(
+fn fold_pid_to_files_w_count<T, K: Eq + Hash>(
     map: impl IntoIterator<Item = (u64, ProcInfo)>,
     key: impl Fn(u64, &ProcInfo) -> K,
     capacity: usize,
     init: impl Fn(u64, ProcInfo) -> T,
-    fold: impl Fn(T, u64, ProcInfo) -> T,
+    fold: impl Fn(&mut T, u64, ProcInfo),
 ) -> FMap<K, (T, usize)> {
     let map = map
         .into_iter()
@@ -454,7 +1410,7 @@ fn fold_pid_to_files_w_count<T: Copy, K: Eq + Hash>(
                 std::collections::hash_map::Entry::Occupied(o) => {
                     let (acc, count) = o.into_mut();
                     *count += info.files.len();
-                    *acc = fold(*acc, pid, info);
+                    fold(acc, pid, info);
                 }
                 std::collections::hash_map::Entry::Vacant(v) => {
                     let len = info.files.len();
@@ -466,6 +1422,28 @@ fn fold_pid_to_files_w_count<T: Copy, K: Eq + Hash>(
         });
     map
 }
+/// Compare by each key in turn, `cmp` compares by a single key
+fn comparator<'a, T>(
+    keys: &'a [SortKey],
+    cmp: impl Fn(&T, &T, Sorting) -> cmp::Ordering + 'a,
+) -> impl Fn(&T, &T) -> cmp::Ordering + 'a {
+    move |a, b| {
+        keys.iter()
+            .map(|key| match cmp(a, b, key.sorting) {
+                ord if key.reverse => ord.reverse(),
+                ord => ord,
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(cmp::Ordering::Equal)
+    }
+}
+fn sort_rows<T>(
+    rows: impl IntoIterator<Item = T>,
+    keys: &[SortKey],
+    cmp: impl Fn(&T, &T, Sorting) -> cmp::Ordering,
+) -> std::vec::IntoIter<T> {
+    rows.into_iter().sorted_unstable_by(comparator(keys, cmp))
+}
 fn print_map<T>(
     order: Ordering,
     mut map: std::vec::IntoIter<T>,
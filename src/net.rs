@@ -0,0 +1,310 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{FMap, IStr, ProcRoot};
+
+// https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt
+
+/// One of the inet socket tables under `/proc/net`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetProto {
+    Tcp,
+    Tcp6,
+    Udp,
+    Udp6,
+}
+
+/// Socket state, the `st` column of the tables.
+/// UDP sockets reuse these values (`Established` when connected, `Close` otherwise)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocketState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+    NewSynRecv,
+    Unknown(u8),
+}
+
+/// A parsed row of `/proc/net/{tcp,tcp6,udp,udp6}`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InetSocket {
+    pub proto: NetProto,
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub state: SocketState,
+    pub uid: u32,
+    pub inode: u64,
+}
+
+impl NetProto {
+    pub const ALL: [NetProto; 4] = [NetProto::Tcp, NetProto::Tcp6, NetProto::Udp, NetProto::Udp6];
+
+    /// The path of the table relative to the proc root
+    #[must_use]
+    pub const fn rel_path(self) -> &'static str {
+        match self {
+            NetProto::Tcp => "net/tcp",
+            NetProto::Tcp6 => "net/tcp6",
+            NetProto::Udp => "net/udp",
+            NetProto::Udp6 => "net/udp6",
+        }
+    }
+    #[must_use]
+    pub const fn is_tcp(self) -> bool {
+        matches!(self, NetProto::Tcp | NetProto::Tcp6)
+    }
+}
+
+impl From<u8> for SocketState {
+    fn from(st: u8) -> Self {
+        match st {
+            0x01 => SocketState::Established,
+            0x02 => SocketState::SynSent,
+            0x03 => SocketState::SynRecv,
+            0x04 => SocketState::FinWait1,
+            0x05 => SocketState::FinWait2,
+            0x06 => SocketState::TimeWait,
+            0x07 => SocketState::Close,
+            0x08 => SocketState::CloseWait,
+            0x09 => SocketState::LastAck,
+            0x0A => SocketState::Listen,
+            0x0B => SocketState::Closing,
+            0x0C => SocketState::NewSynRecv,
+            st => SocketState::Unknown(st),
+        }
+    }
+}
+
+impl InetSocket {
+    /// Whether this socket is bound to or connected on `port`.
+    /// The remote port of an unconnected socket is 0, which is not a connection to port 0
+    #[must_use]
+    pub fn has_port(&self, port: u16) -> bool {
+        self.local.port() == port || (self.remote.port() != 0 && self.remote.port() == port)
+    }
+    /// The name of the link in `/proc/<pid>/fd/` pointing at this socket
+    #[must_use]
+    pub fn fd_link(&self) -> String {
+        format!("socket:[{}]", self.inode)
+    }
+}
+
+/// Read one of the inet socket tables, an unreadable table is treated as empty
+#[must_use]
+pub fn read_net_table(proto: NetProto) -> Vec<InetSocket> {
+    ProcRoot::default().net_table(proto)
+}
+
+/// All tcp/udp sockets of the current network namespace
+#[must_use]
+pub fn inet_sockets() -> Vec<InetSocket> {
+    ProcRoot::default().inet_sockets()
+}
+
+/// Parse the contents of one of the inet socket tables, skipping the header and malformed lines
+#[must_use]
+pub fn parse_net_table(proto: NetProto, content: &str) -> Vec<InetSocket> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| parse_net_line(proto, line))
+        .collect()
+}
+
+fn parse_net_line(proto: NetProto, line: &str) -> Option<InetSocket> {
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    let mut cols = line.split_whitespace();
+    let _sl = cols.next()?;
+    let local = parse_addr(cols.next()?)?;
+    let remote = parse_addr(cols.next()?)?;
+    let state = u8::from_str_radix(cols.next()?, 16).ok()?.into();
+    let mut cols = cols.skip(3);
+    let uid = cols.next()?.parse().ok()?;
+    let _timeout = cols.next()?;
+    let inode = cols.next()?.parse().ok()?;
+    Some(InetSocket {
+        proto,
+        local,
+        remote,
+        state,
+        uid,
+        inode,
+    })
+}
+
+/// `0100007F:1F90` => `127.0.0.1:8080`
+/// The address is the raw network order bytes printed as native endian u32 words
+fn parse_addr(s: &str) -> Option<SocketAddr> {
+    let (ip, port) = s.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let word = |i: usize| {
+        ip.get(i * 8..(i + 1) * 8)
+            .and_then(|w| u32::from_str_radix(w, 16).ok())
+            .map(u32::to_ne_bytes)
+    };
+    let ip = match ip.len() {
+        8 => IpAddr::V4(Ipv4Addr::from(word(0)?)),
+        32 => {
+            let mut octets = [0u8; 16];
+            for (i, chunk) in octets.chunks_exact_mut(4).enumerate() {
+                chunk.copy_from_slice(&word(i)?);
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// The inode of a `socket:[N]` fd link
+#[must_use]
+pub fn socket_inode(file: &str) -> Option<u64> {
+    file.strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// `Type` column of `/proc/net/unix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum UnixSocketType {
+    Stream,
+    Dgram,
+    SeqPacket,
+    Unknown(u16),
+}
+
+/// `St` column of `/proc/net/unix`, the `socket_state` of the socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum UnixSocketState {
+    Free,
+    Unconnected,
+    Connecting,
+    Connected,
+    Disconnecting,
+    Unknown(u8),
+}
+
+/// A parsed row of `/proc/net/unix`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct UnixSocket {
+    pub inode: u64,
+    /// The bound path, abstract names are prefixed with `@`
+    pub path: Option<IStr>,
+    pub kind: UnixSocketType,
+    pub state: UnixSocketState,
+    pub flags: u32,
+}
+
+impl From<u16> for UnixSocketType {
+    fn from(ty: u16) -> Self {
+        match ty {
+            1 => UnixSocketType::Stream,
+            2 => UnixSocketType::Dgram,
+            5 => UnixSocketType::SeqPacket,
+            ty => UnixSocketType::Unknown(ty),
+        }
+    }
+}
+
+impl From<UnixSocketType> for u16 {
+    fn from(ty: UnixSocketType) -> Self {
+        match ty {
+            UnixSocketType::Stream => 1,
+            UnixSocketType::Dgram => 2,
+            UnixSocketType::SeqPacket => 5,
+            UnixSocketType::Unknown(ty) => ty,
+        }
+    }
+}
+
+impl From<u8> for UnixSocketState {
+    fn from(st: u8) -> Self {
+        match st {
+            0 => UnixSocketState::Free,
+            1 => UnixSocketState::Unconnected,
+            2 => UnixSocketState::Connecting,
+            3 => UnixSocketState::Connected,
+            4 => UnixSocketState::Disconnecting,
+            st => UnixSocketState::Unknown(st),
+        }
+    }
+}
+
+impl From<UnixSocketState> for u8 {
+    fn from(st: UnixSocketState) -> Self {
+        match st {
+            UnixSocketState::Free => 0,
+            UnixSocketState::Unconnected => 1,
+            UnixSocketState::Connecting => 2,
+            UnixSocketState::Connected => 3,
+            UnixSocketState::Disconnecting => 4,
+            UnixSocketState::Unknown(st) => st,
+        }
+    }
+}
+
+impl UnixSocket {
+    /// `__SO_ACCEPTCON`, set on sockets that called listen(2)
+    pub const ACCEPTCON: u32 = 1 << 16;
+
+    #[must_use]
+    pub const fn is_listening(&self) -> bool {
+        self.flags & Self::ACCEPTCON != 0
+    }
+}
+
+/// Read `/proc/net/unix` keyed by inode, an unreadable table is treated as empty
+#[must_use]
+pub fn read_unix_table() -> FMap<u64, UnixSocket> {
+    ProcRoot::default().unix_table()
+}
+
+/// Parse the contents of `/proc/net/unix`, skipping the header and malformed lines
+#[must_use]
+pub fn parse_unix_table(content: &str) -> FMap<u64, UnixSocket> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(parse_unix_line)
+        .map(|s| (s.inode, s))
+        .collect()
+}
+
+fn parse_unix_line(mut line: &str) -> Option<UnixSocket> {
+    // Num RefCount Protocol Flags Type St Inode Path
+    let mut col = || {
+        let (col, rest) = line
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .unwrap_or((line.trim_start(), ""));
+        line = rest;
+        (!col.is_empty()).then_some(col)
+    };
+    let _num = col()?;
+    let _refcount = col()?;
+    let _protocol = col()?;
+    let flags = u32::from_str_radix(col()?, 16).ok()?;
+    let kind = u16::from_str_radix(col()?, 16).ok()?.into();
+    let state = u8::from_str_radix(col()?, 16).ok()?.into();
+    let inode = col()?.parse().ok()?;
+    // The path is the rest of the line and may contain spaces
+    let path = line.trim_start();
+    Some(UnixSocket {
+        inode,
+        path: (!path.is_empty()).then(|| path.into()),
+        kind,
+        state,
+        flags,
+    })
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use itertools::chain;
+
+use crate::{dev_major_minor, fmap, get_pid_mountinfo, Data, FMap, FSet, FileRole, IStr};
+
+/// A deleted file that is still in use, so its blocks can't be freed yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PinnedFile {
+    pub pid: u64,
+    pub role: FileRole,
+    pub file: IStr,
+    /// `st_dev` of the file
+    pub dev: u64,
+    pub ino: u64,
+    /// Space allocated to the file, `st_blocks` in bytes
+    pub bytes: u64,
+}
+
+/// The space pinned by deleted files on one filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PinnedFs {
+    /// (major, minor) of the device
+    pub dev: (u32, u32),
+    /// Where the filesystem is mounted, as seen by one of the processes holding its files
+    pub mount_point: Option<String>,
+    /// Distinct files, a file held by several processes is counted once
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// The space pinned by the deleted files of one process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PinnedProc {
+    pub pid: u64,
+    pub name: Option<IStr>,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Deleted files that are still in use and the space they pin, see [`Data::pinned`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PinnedReport {
+    /// Sorted by pid
+    pub files: Vec<PinnedFile>,
+    /// Sorted by bytes, largest first
+    pub filesystems: Vec<PinnedFs>,
+    /// Sorted by bytes, largest first
+    pub procs: Vec<PinnedProc>,
+}
+
+impl Data {
+    /// The deleted files held through a descriptor or the cwd, root or exe link of a process,
+    /// sized by stat(2) on that link in `/proc/<pid>`.
+    /// Deleted mappings are skipped, stat(2) on `map_files` needs `CAP_SYS_ADMIN`
+    #[must_use]
+    pub fn pinned_files(&self) -> Vec<PinnedFile> {
+        let mut pinned = Vec::new();
+        for (&pid, info) in &self.pid_to_files {
+            let records = info.records.iter().filter(|r| r.deleted);
+            let fds = info.fds.iter().filter(|f| f.deleted);
+            let files = chain!(
+                records.map(|r| (r.role, &r.file)),
+                fds.map(|f| (FileRole::Fd(f.fd), &f.file))
+            );
+            for (role, file) in files {
+                let Some(link) = self.proc_root.link_path(pid, role) else {
+                    continue;
+                };
+                // The process exited or closed it since the scan
+                let Ok(meta) = fs::metadata(link) else {
+                    continue;
+                };
+                pinned.push(PinnedFile {
+                    pid,
+                    role,
+                    file: file.clone(),
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                    bytes: meta.blocks() * 512,
+                });
+            }
+        }
+        pinned.sort_unstable_by_key(|f| (f.pid, f.role));
+        pinned
+    }
+
+    /// The space pinned by deleted files per filesystem and per process, like lsof's `+L1`
+    #[must_use]
+    pub fn pinned(&self) -> PinnedReport {
+        let files = self.pinned_files();
+        // dev => (inodes, bytes, a pid to read mountinfo from)
+        let mut by_dev: FMap<u64, (FSet<u64>, u64, u64)> = fmap(0);
+        let mut by_pid: FMap<u64, (FSet<(u64, u64)>, u64)> = fmap(0);
+        for f in &files {
+            let (inodes, bytes, _) = by_dev
+                .entry(f.dev)
+                .or_insert_with(|| (FSet::default(), 0, f.pid));
+            if inodes.insert(f.ino) {
+                *bytes += f.bytes;
+            }
+            let (inodes, bytes) = by_pid.entry(f.pid).or_default();
+            if inodes.insert((f.dev, f.ino)) {
+                *bytes += f.bytes;
+            }
+        }
+
+        let mut filesystems = by_dev
+            .into_iter()
+            .map(|(dev, (inodes, bytes, pid))| {
+                let dev = dev_major_minor(dev);
+                // Prefer the mount of the whole filesystem over bind mounts of its directories
+                let mount_point = get_pid_mountinfo(self.proc_root.pid_path(pid))
+                    .into_iter()
+                    .filter(|m| m.dev == dev)
+                    .min_by_key(|m| m.root != "/")
+                    .map(|m| m.mount_point);
+                PinnedFs {
+                    dev,
+                    mount_point,
+                    files: inodes.len(),
+                    bytes,
+                }
+            })
+            .collect::<Vec<_>>();
+        filesystems.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then(a.dev.cmp(&b.dev)));
+        let mut procs = by_pid
+            .into_iter()
+            .map(|(pid, (inodes, bytes))| PinnedProc {
+                pid,
+                name: self.pid_to_files.get(&pid).and_then(|i| i.name.clone()),
+                files: inodes.len(),
+                bytes,
+            })
+            .collect::<Vec<_>>();
+        procs.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then(a.pid.cmp(&b.pid)));
+        PinnedReport {
+            files,
+            filesystems,
+            procs,
+        }
+    }
+}
@@ -1,9 +1,14 @@
+use std::fmt::Display;
 use std::fs::read_to_string;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime};
 
-use crate::{fmap, FMap, StrLeakExt};
+use crate::{fmap, FMap, ProcRoot};
 use anyhow::{Context, Result};
 
 // https://github.com/eminence/procfs/blob/master/procfs-core/src/process/stat.rs
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Stat {
     /// The process ID.
     pub pid: i32,
@@ -205,32 +210,427 @@ pub struct Stat {
     pub exit_code: Option<i32>,
 }
 
+/// Process state, see [`Stat::state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProcState {
+    /// `R`
+    Running,
+    /// `S`, interruptible sleep
+    Sleeping,
+    /// `D`, uninterruptible disk sleep
+    Waiting,
+    /// `Z`
+    Zombie,
+    /// `T`, stopped on a signal
+    Stopped,
+    /// `t`, stopped by a debugger
+    Tracing,
+    /// `X` or `x`
+    Dead,
+    /// `K` (Linux 2.6.33 to 3.13)
+    Wakekill,
+    /// `W`, paging before 2.6.0 and waking until 3.13
+    Waking,
+    /// `P` (Linux 3.9 to 3.13)
+    Parked,
+    /// `I`, idle kernel thread (since Linux 4.14)
+    Idle,
+    Unknown(char),
+}
+
+bitflags::bitflags! {
+    /// The `PF_*` kernel flags of a process, see [`Stat::flags`]
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct StatFlags: u32 {
+        const IDLE = 0x0000_0002;
+        const EXITING = 0x0000_0004;
+        const VCPU = 0x0000_0010;
+        const WQ_WORKER = 0x0000_0020;
+        const FORKNOEXEC = 0x0000_0040;
+        const MCE_PROCESS = 0x0000_0080;
+        const SUPERPRIV = 0x0000_0100;
+        const DUMPCORE = 0x0000_0200;
+        const SIGNALED = 0x0000_0400;
+        const MEMALLOC = 0x0000_0800;
+        const NPROC_EXCEEDED = 0x0000_1000;
+        const USED_MATH = 0x0000_2000;
+        const NOFREEZE = 0x0000_8000;
+        const KSWAPD = 0x0002_0000;
+        const KTHREAD = 0x0020_0000;
+        const RANDOMIZE = 0x0040_0000;
+        const NO_SETAFFINITY = 0x0400_0000;
+        const MCE_EARLY = 0x0800_0000;
+        const SUSPEND_TASK = 0x8000_0000;
+
+        // Keep any bits we don't know about
+        const _ = !0;
+    }
+}
+
+impl Display for ProcState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProcState::Running => "running",
+            ProcState::Sleeping => "sleeping",
+            ProcState::Waiting => "disk-sleep",
+            ProcState::Zombie => "zombie",
+            ProcState::Stopped => "stopped",
+            ProcState::Tracing => "tracing-stop",
+            ProcState::Dead => "dead",
+            ProcState::Wakekill => "wakekill",
+            ProcState::Waking => "waking",
+            ProcState::Parked => "parked",
+            ProcState::Idle => "idle",
+            ProcState::Unknown(c) => return write!(f, "{c}"),
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl From<char> for ProcState {
+    fn from(c: char) -> Self {
+        match c {
+            'R' => ProcState::Running,
+            'S' => ProcState::Sleeping,
+            'D' => ProcState::Waiting,
+            'Z' => ProcState::Zombie,
+            'T' => ProcState::Stopped,
+            't' => ProcState::Tracing,
+            'X' | 'x' => ProcState::Dead,
+            'K' => ProcState::Wakekill,
+            'W' => ProcState::Waking,
+            'P' => ProcState::Parked,
+            'I' => ProcState::Idle,
+            c => ProcState::Unknown(c),
+        }
+    }
+}
+
+impl Stat {
+    #[must_use]
+    pub fn state(&self) -> ProcState {
+        self.state.into()
+    }
+    /// The controlling terminal as `(major, minor)`, `None` if there is no terminal
+    #[must_use]
+    pub fn tty_nr(&self) -> Option<(u32, u32)> {
+        #[allow(clippy::cast_sign_loss)]
+        let tty_nr = self.tty_nr as u32;
+        let major = (tty_nr >> 8) & 0xfff;
+        let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xf_ff00);
+        (tty_nr != 0).then_some((major, minor))
+    }
+    #[must_use]
+    pub fn flags(&self) -> StatFlags {
+        StatFlags::from_bits_retain(self.flags)
+    }
+    /// Time from system boot until the process started
+    #[must_use]
+    pub fn starttime_since_boot(&self) -> Duration {
+        ticks_to_duration(self.starttime)
+    }
+    /// Wall clock time the process started, `None` if the boot time is unavailable
+    #[must_use]
+    pub fn start_time(&self, proc_root: &ProcRoot) -> Option<SystemTime> {
+        Some(boot_time(proc_root)? + self.starttime_since_boot())
+    }
+}
+
+/// Parse the contents of `/proc/<pid>/stat`.
+///
+/// The comm field is everything between the first `(` and the last `)`,
+/// so names containing spaces or parentheses are handled.
+/// Fields added in newer kernels are `None` when missing.
+impl FromStr for Stat {
+    type Err = anyhow::Error;
+
+    fn from_str(stat: &str) -> Result<Self> {
+        let (pid, rest) = stat.split_once('(').context("stat: missing comm")?;
+        let (comm, rest) = rest.rsplit_once(')').context("stat: missing comm")?;
+        let mut fields = rest.split_whitespace();
+        macro_rules! next {
+            () => {
+                fields
+                    .next()
+                    .context("stat: missing field")?
+                    .parse()
+                    .context("stat: bad field")?
+            };
+        }
+        macro_rules! opt {
+            () => {
+                fields.next().and_then(|f| f.parse().ok())
+            };
+        }
+        Ok(Stat {
+            pid: pid.trim().parse().context("stat: bad pid")?,
+            comm: comm.to_owned(),
+            state: next!(),
+            ppid: next!(),
+            pgrp: next!(),
+            session: next!(),
+            tty_nr: next!(),
+            tpgid: next!(),
+            flags: next!(),
+            minflt: next!(),
+            cminflt: next!(),
+            majflt: next!(),
+            cmajflt: next!(),
+            utime: next!(),
+            stime: next!(),
+            cutime: next!(),
+            cstime: next!(),
+            priority: next!(),
+            nice: next!(),
+            num_threads: next!(),
+            itrealvalue: next!(),
+            starttime: next!(),
+            vsize: next!(),
+            rss: next!(),
+            rsslim: next!(),
+            startcode: next!(),
+            endcode: next!(),
+            startstack: next!(),
+            kstkesp: next!(),
+            kstkeip: next!(),
+            signal: next!(),
+            blocked: next!(),
+            sigignore: next!(),
+            sigcatch: next!(),
+            wchan: next!(),
+            nswap: next!(),
+            cnswap: next!(),
+            exit_signal: opt!(),
+            processor: opt!(),
+            rt_priority: opt!(),
+            policy: opt!(),
+            delayacct_blkio_ticks: opt!(),
+            guest_time: opt!(),
+            cguest_time: opt!(),
+            start_data: opt!(),
+            end_data: opt!(),
+            start_brk: opt!(),
+            arg_start: opt!(),
+            arg_end: opt!(),
+            env_start: opt!(),
+            env_end: opt!(),
+            exit_code: opt!(),
+        })
+    }
+}
+
+/// Clock ticks per second, the unit of the time fields of [`Stat`]
+#[must_use]
+pub fn ticks_per_second() -> u64 {
+    static TICKS: OnceLock<u64> = OnceLock::new();
+    *TICKS.get_or_init(|| {
+        // SAFETY: sysconf has no preconditions
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        u64::try_from(ticks).ok().filter(|&t| t > 0).unwrap_or(100)
+    })
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let tps = ticks_per_second();
+    Duration::from_secs(ticks / tps)
+        + Duration::from_secs(ticks % tps) / u32::try_from(tps).unwrap_or(100)
+}
+
+/// System boot time, the `btime` line of `<proc_root>/stat`, read once for each proc root
+#[must_use]
+pub fn boot_time(proc_root: &ProcRoot) -> Option<SystemTime> {
+    static BOOT_TIME: OnceLock<Mutex<FMap<ProcRoot, Option<SystemTime>>>> = OnceLock::new();
+    let mut boot_times = BOOT_TIME
+        .get_or_init(|| Mutex::new(fmap(1)))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    *boot_times.entry(proc_root.clone()).or_insert_with(|| {
+        let stat = read_to_string(proc_root.join("stat")).ok()?;
+        let btime = stat.lines().find_map(|l| l.strip_prefix("btime "))?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(btime.trim().parse().ok()?))
+    })
+}
+
+/// Get the parsed stat for a specific pid (given as the proc path `/proc/{pid}`)
 #[tracing::instrument(level = "trace")]
 #[must_use]
-pub fn get_pid_info_status(path: String) -> FMap<String, String> {
-    let path = path + "/status";
-    // TODO: better parser for this
-    // can make this lazy because we only need "Name"
-    // Also, parse to a struct
-    // also should use /stat/ because parsing
-    let mut map: FMap<String, String> = fmap(0);
-    if path.is_empty() {
-        return map;
+pub fn get_pid_stat(path: String) -> Option<Stat> {
+    let path = path + "/stat";
+    read_to_string(path).ok()?.parse().ok()
+}
+
+// https://man7.org/linux/man-pages/man5/proc_pid_status.5.html
+/// The parsed `/proc/<pid>/status`, memory sizes are in bytes
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// Command run by this process, as in `Stat::comm`.
+    pub name: String,
+    /// Process umask (since Linux 4.7).
+    pub umask: Option<u32>,
+    /// Current state of the process, the first character of the `State` line.
+    pub state: char,
+    /// Thread group ID (i.e., Process ID).
+    pub tgid: i32,
+    /// Thread ID.
+    pub pid: i32,
+    /// PID of parent process.
+    pub ppid: i32,
+    /// PID of process tracing this process (0 if not being traced).
+    pub tracer_pid: i32,
+    /// Real, effective, saved set, and filesystem UIDs.
+    pub uid: Ids,
+    /// Real, effective, saved set, and filesystem GIDs.
+    pub gid: Ids,
+    /// Number of file descriptor slots currently allocated.
+    pub fd_size: u64,
+    /// Supplementary group list.
+    pub groups: Vec<u32>,
+    /// Thread ID in each of the PID namespaces of which this thread is a member,
+    /// from the outermost namespace (since Linux 4.1).
+    pub ns_pid: Vec<i32>,
+    /// Peak virtual memory size.
+    pub vm_peak: Option<u64>,
+    /// Virtual memory size.
+    pub vm_size: Option<u64>,
+    /// Peak resident set size ("high water mark").
+    pub vm_hwm: Option<u64>,
+    /// Resident set size.
+    pub vm_rss: Option<u64>,
+    /// Swapped-out virtual memory size by anonymous private pages.
+    pub vm_swap: Option<u64>,
+    /// Number of threads in the process containing this thread.
+    pub threads: u64,
+    /// Mask of signals pending for the thread.
+    pub sig_pnd: u64,
+    /// Mask of signals pending for the process as a whole.
+    pub shd_pnd: u64,
+    /// Mask of signals being blocked.
+    pub sig_blk: u64,
+    /// Mask of signals being ignored.
+    pub sig_ign: u64,
+    /// Mask of signals being caught.
+    pub sig_cgt: u64,
+    /// Inheritable capability set.
+    pub cap_inh: u64,
+    /// Permitted capability set.
+    pub cap_prm: u64,
+    /// Effective capability set.
+    pub cap_eff: u64,
+    /// Capability bounding set.
+    pub cap_bnd: u64,
+    /// Ambient capability set (since Linux 4.3).
+    pub cap_amb: Option<u64>,
+}
+
+/// The four ids of the `Uid` and `Gid` lines of `/proc/<pid>/status`
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ids {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+    pub fs: u32,
+}
+
+impl FromStr for Ids {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut ids = s.split_whitespace().map(str::parse);
+        let mut next = || {
+            ids.next()
+                .context("status: missing id")?
+                .context("status: bad id")
+        };
+        Ok(Ids {
+            real: next()?,
+            effective: next()?,
+            saved: next()?,
+            fs: next()?,
+        })
     }
-    match read_to_string(path) {
-        Ok(content) => {
-            for line in content.lines() {
-                if let Some((key, value)) = line.split_once(':') {
-                    map.insert(key.trim().to_owned(), value.trim().to_owned());
+}
+
+/// Parse the contents of `/proc/<pid>/status`, unknown lines are ignored
+impl FromStr for Status {
+    type Err = anyhow::Error;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let mut status = Status::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            let hex =
+                || u64::from_str_radix(value, 16).with_context(|| format!("status: bad {key}"));
+            macro_rules! num {
+                () => {
+                    value
+                        .parse()
+                        .with_context(|| format!("status: bad {key}"))?
+                };
+            }
+            // `VmRSS:	    1440 kB`
+            let bytes = || -> Result<u64> {
+                let (n, unit) = value.split_once(' ').unwrap_or((value, ""));
+                let n: u64 = n.parse().with_context(|| format!("status: bad {key}"))?;
+                Ok(match unit.trim() {
+                    "kB" => n * 1024,
+                    _ => n,
+                })
+            };
+            match key {
+                "Name" => value.clone_into(&mut status.name),
+                "Umask" => status.umask = Some(u32::from_str_radix(value, 8)?),
+                "State" => status.state = value.chars().next().unwrap_or('?'),
+                "Tgid" => status.tgid = num!(),
+                "Pid" => status.pid = num!(),
+                "PPid" => status.ppid = num!(),
+                "TracerPid" => status.tracer_pid = num!(),
+                "Uid" => status.uid = value.parse()?,
+                "Gid" => status.gid = value.parse()?,
+                "FDSize" => status.fd_size = num!(),
+                "Groups" => {
+                    status.groups = value
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?;
                 }
+                "NSpid" => {
+                    status.ns_pid = value
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?;
+                }
+                "VmPeak" => status.vm_peak = Some(bytes()?),
+                "VmSize" => status.vm_size = Some(bytes()?),
+                "VmHWM" => status.vm_hwm = Some(bytes()?),
+                "VmRSS" => status.vm_rss = Some(bytes()?),
+                "VmSwap" => status.vm_swap = Some(bytes()?),
+                "Threads" => status.threads = num!(),
+                "SigPnd" => status.sig_pnd = hex()?,
+                "ShdPnd" => status.shd_pnd = hex()?,
+                "SigBlk" => status.sig_blk = hex()?,
+                "SigIgn" => status.sig_ign = hex()?,
+                "SigCgt" => status.sig_cgt = hex()?,
+                "CapInh" => status.cap_inh = hex()?,
+                "CapPrm" => status.cap_prm = hex()?,
+                "CapEff" => status.cap_eff = hex()?,
+                "CapBnd" => status.cap_bnd = hex()?,
+                "CapAmb" => status.cap_amb = Some(hex()?),
+                _ => {}
             }
         }
-        Err(_) => {
-            return map;
-            // println!("Error reading file: {}", e);
-        }
+        Ok(status)
     }
-    map
+}
+
+/// Get the parsed status for a specific pid (given as the proc path `/proc/{pid}`)
+#[tracing::instrument(level = "trace")]
+#[must_use]
+pub fn get_pid_status(path: String) -> Option<Status> {
+    let path = path + "/status";
+    read_to_string(path).ok()?.parse().ok()
 }
 
 // https://github.com/heim-rs/heim/issues/154
@@ -238,28 +638,255 @@ pub fn get_pid_info_status(path: String) -> FMap<String, String> {
 /// Get the name for a specific pid (given as the proc path `/proc/{pid}`)
 #[tracing::instrument(level = "trace")]
 #[must_use]
-pub fn get_pid_name(path: String) -> Option<&'static str> {
+pub fn get_pid_name(path: String) -> Option<String> {
     let path = path + "/stat";
     let stat = read_to_string(path).ok()?;
     let stat = stat.trim();
     let (_, name) = stat.split_once('(')?;
-    let (name, _) = name.split_once(')')?;
+    // The name can itself contain ')'
+    let (name, _) = name.rsplit_once(')')?;
 
-    Some(name.leak_str())
+    Some(name.to_owned())
 }
 #[tracing::instrument(level = "trace")]
 #[must_use]
-pub fn get_pid_cmdline(path: String) -> Option<&'static str> {
+pub fn get_pid_cmdline(path: String) -> Option<String> {
     let path = path + "/cmdline";
     let stat = read_to_string(path).ok()?;
     let stat = stat.trim();
     let (_, name) = stat.split_once(' ')?;
 
-    Some(name.leak_str())
+    Some(name.to_owned())
 }
 #[tracing::instrument(level = "trace")]
 #[must_use]
-pub fn get_pid_name_status(proc_path_str: String) -> Option<&'static str> {
-    let other_info = get_pid_info_status(proc_path_str.clone());
-    other_info.get("Name").cloned().map(StrLeakExt::leak_str)
+pub fn get_pid_name_status(proc_path_str: String) -> Option<String> {
+    get_pid_status(proc_path_str).map(|status| status.name)
+}
+
+bitflags::bitflags! {
+    /// The `perms` column of `/proc/<pid>/maps`
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct MapPerms: u8 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+        /// `s`, otherwise the mapping is private (`p`)
+        const SHARED = 1 << 3;
+    }
+}
+
+// https://man7.org/linux/man-pages/man5/proc_pid_maps.5.html
+/// A line of `/proc/<pid>/maps`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MapEntry {
+    /// Start of the address range
+    pub start: u64,
+    /// End of the address range (exclusive)
+    pub end: u64,
+    pub perms: MapPerms,
+    /// Offset into the file
+    pub offset: u64,
+    /// (major, minor) of the device holding the file
+    pub dev: (u32, u32),
+    /// Inode on that device, 0 for anonymous mappings
+    pub inode: u64,
+    /// The mapped file or a pseudo-path like `[heap]`, `None` for anonymous mappings.
+    ///
+    /// The ` (deleted)` suffix is stripped, see `deleted`.
+    pub path: Option<String>,
+    /// The mapped file has been unlinked
+    pub deleted: bool,
+}
+
+impl MapEntry {
+    /// Size of the mapping in bytes
+    #[must_use]
+    pub const fn size(&self) -> u64 {
+        self.end - self.start
+    }
+    #[must_use]
+    pub const fn is_executable(&self) -> bool {
+        self.perms.contains(MapPerms::EXEC)
+    }
+    /// Whether this maps a real file rather than `[heap]`, `[stack]`, `[vdso]`, ...
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        self.path.as_ref().is_some_and(|p| !p.starts_with('['))
+    }
+}
+
+impl std::fmt::Display for MapPerms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |flag, c| if self.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}",
+            flag(MapPerms::READ, 'r'),
+            flag(MapPerms::WRITE, 'w'),
+            flag(MapPerms::EXEC, 'x'),
+            if self.contains(MapPerms::SHARED) {
+                's'
+            } else {
+                'p'
+            },
+        )
+    }
+}
+
+/// Get the memory mappings for a specific pid (given as the proc path `/proc/{pid}`)
+#[tracing::instrument(level = "trace")]
+#[must_use]
+pub fn get_pid_maps(path: String) -> Vec<MapEntry> {
+    let path = path + "/maps";
+    let Ok(content) = read_to_string(path) else {
+        return Vec::new();
+    };
+    parse_maps(&content)
+}
+
+/// Parse the contents of `/proc/<pid>/maps`, skipping malformed lines
+#[must_use]
+pub fn parse_maps(content: &str) -> Vec<MapEntry> {
+    content.lines().filter_map(parse_map_line).collect()
+}
+
+fn parse_map_line(line: &str) -> Option<MapEntry> {
+    // address perms offset dev inode pathname
+    let mut cols = line.splitn(6, ' ');
+    let (start, end) = cols.next()?.split_once('-')?;
+    let perms = cols.next()?.as_bytes();
+    let offset = cols.next()?;
+    let (major, minor) = cols.next()?.split_once(':')?;
+    let inode = cols.next()?.parse().ok()?;
+    // The pathname is padded to a column and may itself contain spaces
+    let path = cols.next().unwrap_or_default().trim_start();
+    let (path, deleted) = split_deleted(path);
+
+    let [r, w, x, s] = perms else {
+        return None;
+    };
+    let mut flags = MapPerms::empty();
+    flags.set(MapPerms::READ, *r == b'r');
+    flags.set(MapPerms::WRITE, *w == b'w');
+    flags.set(MapPerms::EXEC, *x == b'x');
+    flags.set(MapPerms::SHARED, *s == b's');
+
+    Some(MapEntry {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        perms: flags,
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        dev: (
+            u32::from_str_radix(major, 16).ok()?,
+            u32::from_str_radix(minor, 16).ok()?,
+        ),
+        inode,
+        path: (!path.is_empty()).then(|| path.to_owned()),
+        deleted,
+    })
+}
+
+/// The mappings of a single file, merged
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MappedFile {
+    /// Total mapped bytes
+    pub size: u64,
+    /// Union of the permissions of every mapping
+    pub perms: MapPerms,
+    pub deleted: bool,
+}
+
+/// Merge the mappings of each file, skipping anonymous and pseudo-path mappings
+#[must_use]
+pub fn mapped_files(maps: &[MapEntry]) -> FMap<&str, MappedFile> {
+    let mut files: FMap<&str, MappedFile> = fmap(maps.len());
+    for map in maps.iter().filter(|m| m.is_file()) {
+        let Some(path) = map.path.as_deref() else {
+            continue;
+        };
+        let file = files.entry(path).or_default();
+        file.size += map.size();
+        file.perms |= map.perms;
+        file.deleted |= map.deleted;
+    }
+    files
+}
+
+/// Split off the ` (deleted)` the kernel appends to the paths of unlinked files in links and `maps`
+#[must_use]
+pub fn split_deleted(path: &str) -> (&str, bool) {
+    match path.strip_suffix(" (deleted)") {
+        Some(path) => (path, true),
+        None => (path, false),
+    }
+}
+
+// https://man7.org/linux/man-pages/man5/proc_pid_mountinfo.5.html
+/// A line of `/proc/<pid>/mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MountInfo {
+    pub mnt_id: u64,
+    /// (major, minor) of the device, as in `st_dev`
+    pub dev: (u32, u32),
+    /// The directory of the filesystem mounted here
+    pub root: String,
+    pub mount_point: String,
+    pub fs_type: String,
+    pub source: String,
+}
+
+/// Get the mounts seen by a specific pid (given as the proc path `/proc/{pid}`)
+#[tracing::instrument(level = "trace")]
+#[must_use]
+pub fn get_pid_mountinfo(path: String) -> Vec<MountInfo> {
+    let path = path + "/mountinfo";
+    let Ok(content) = read_to_string(path) else {
+        return Vec::new();
+    };
+    parse_mountinfo(&content)
+}
+
+/// Parse the contents of `/proc/<pid>/mountinfo`, skipping malformed lines
+#[must_use]
+pub fn parse_mountinfo(content: &str) -> Vec<MountInfo> {
+    content.lines().filter_map(parse_mountinfo_line).collect()
+}
+
+fn parse_mountinfo_line(line: &str) -> Option<MountInfo> {
+    // mnt_id parent_id major:minor root mount_point options [optional fields...] - fs_type source super_options
+    let (mount, fs) = line.split_once(" - ")?;
+    let mut cols = mount.split(' ');
+    let mnt_id = cols.next()?.parse().ok()?;
+    let _parent = cols.next()?;
+    let (major, minor) = cols.next()?.split_once(':')?;
+    let root = unescape_octal(cols.next()?);
+    let mount_point = unescape_octal(cols.next()?);
+    let mut cols = fs.split(' ');
+    Some(MountInfo {
+        mnt_id,
+        dev: (major.parse().ok()?, minor.parse().ok()?),
+        root,
+        mount_point,
+        fs_type: cols.next()?.to_owned(),
+        source: unescape_octal(cols.next()?),
+    })
+}
+
+/// Spaces, tabs, newlines and backslashes are written as `\ooo` in mountinfo
+fn unescape_octal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some((before, after)) = rest.split_once('\\') {
+        out.push_str(before);
+        if let Some(c) = after.get(..3).and_then(|o| u8::from_str_radix(o, 8).ok()) {
+            out.push(char::from(c));
+            rest = &after[3..];
+        } else {
+            out.push('\\');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
 }
@@ -0,0 +1,176 @@
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use itertools::Itertools;
+
+use crate::{Data, FileRole, PinnedFile};
+
+/// A deleted file to recover, by the path it had or by the descriptor holding it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RecoverSource {
+    /// The path of the file before it was deleted
+    Path(String),
+    /// `PID:FD`
+    Fd { pid: u64, fd: u32 },
+}
+
+/// A file copied by [`Data::recover`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovered {
+    /// The deleted file and the process it was read through
+    pub from: PinnedFile,
+    /// Length of the copy, holes included
+    pub len: u64,
+}
+
+impl FromStr for RecoverSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with('/') {
+            return Ok(RecoverSource::Path(s.to_owned()));
+        }
+        let (pid, fd) = s
+            .split_once(':')
+            .and_then(|(pid, fd)| Some((pid.parse().ok()?, fd.parse().ok()?)))
+            .ok_or_else(|| {
+                anyhow!("bad file to recover {s}, expected an absolute path or PID:FD")
+            })?;
+        Ok(RecoverSource::Fd { pid, fd })
+    }
+}
+
+impl Display for RecoverSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecoverSource::Path(path) => write!(f, "{path}"),
+            RecoverSource::Fd { pid, fd } => write!(f, "{pid}:{fd}"),
+        }
+    }
+}
+
+impl Data {
+    /// The deleted file named by `source`, among [`Data::pinned_files`]
+    ///
+    /// # Errors
+    /// anyhow: no deleted file matches `source`, or its path was held by several different deleted files
+    pub fn find_deleted(&self, source: &RecoverSource) -> Result<PinnedFile> {
+        let pinned = self.pinned_files();
+        match source {
+            RecoverSource::Path(path) => {
+                let mut held = pinned.into_iter().filter(|f| &*f.file == path);
+                let first = held
+                    .next()
+                    .with_context(|| format!("no deleted file {path} is open"))?;
+                let others = held
+                    .filter(|f| (f.dev, f.ino) != (first.dev, first.ino))
+                    .collect_vec();
+                if !others.is_empty() {
+                    let fds = [&first]
+                        .into_iter()
+                        .chain(&others)
+                        .map(|f| format!("{}:{}", f.pid, f.role));
+                    bail!(
+                        "{path} was deleted more than once, pick one of {}",
+                        fds.format(", ")
+                    );
+                }
+                Ok(first)
+            }
+            &RecoverSource::Fd { pid, fd } => {
+                if let Some(f) = pinned
+                    .into_iter()
+                    .find(|f| f.pid == pid && f.role == FileRole::Fd(fd))
+                {
+                    return Ok(f);
+                }
+                let info = self
+                    .pid_to_files
+                    .get(&pid)
+                    .with_context(|| format!("process {pid} not found in lsof"))?;
+                match info.fds.iter().find(|f| f.fd == fd) {
+                    Some(f) if !f.deleted => {
+                        bail!("{pid}:{fd} is not deleted, copy {} instead", f.file)
+                    }
+                    Some(_) => bail!("{pid}:{fd} was closed since the scan"),
+                    None => bail!("{pid}:{fd} is not open"),
+                }
+            }
+        }
+    }
+
+    /// Copy the contents of a deleted file that is still open to the new file `dest`, through its link in `/proc/<pid>`.
+    /// Holes are kept where the filesystem reports them, and a file still being written is copied as it is now
+    ///
+    /// # Errors
+    /// anyhow: see [`Data::find_deleted`], `dest` already exists, or the copy failed
+    pub fn recover(&self, source: &RecoverSource, dest: impl AsRef<Path>) -> Result<Recovered> {
+        let dest = dest.as_ref();
+        let from = self.find_deleted(source)?;
+        let link = self
+            .proc_root
+            .link_path(from.pid, from.role)
+            .context("only files held through a link can be recovered")?;
+        let src = File::open(&link).with_context(|| format!("could not open {link}"))?;
+        let len = copy_sparse(&src, dest)
+            .with_context(|| format!("could not recover {source} to {}", dest.display()))?;
+        Ok(Recovered { from, len })
+    }
+}
+
+/// Copy `src` to `dest`, which must not exist yet, skipping the holes of `src`.
+/// `dest` gets the permissions of `src` without the setuid, setgid and sticky bits, and is removed if the copy fails
+///
+/// # Errors
+/// io: `dest` exists or can't be created, or reading or writing failed
+pub fn copy_sparse(src: &File, dest: &Path) -> io::Result<u64> {
+    let meta = src.metadata()?;
+    let mut out = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(meta.mode() & 0o777)
+        .open(dest)?;
+    let len = meta.len();
+    copy_data(src, &mut out, len).inspect_err(|_| {
+        // So the copy can be retried, `create_new` would refuse the partial one
+        let _ = fs::remove_file(dest);
+    })?;
+    Ok(len)
+}
+
+fn copy_data(mut src: &File, out: &mut File, len: u64) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < len {
+        let data = match seek(src, pos, libc::SEEK_DATA) {
+            Ok(data) => data,
+            // Only holes are left
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            // Holes are not supported, copy the rest
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) && pos == 0 => {
+                io::copy(&mut src, out)?;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let hole = seek(src, data, libc::SEEK_HOLE)?.min(len);
+        src.seek(SeekFrom::Start(data))?;
+        out.seek(SeekFrom::Start(data))?;
+        io::copy(&mut src.take(hole - data), out)?;
+        pos = hole;
+    }
+    // Keep a trailing hole
+    out.set_len(len)
+}
+
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    let offset = libc::off_t::try_from(offset).map_err(io::Error::other)?;
+    // SAFETY: lseek only moves the offset of the descriptor
+    let pos = unsafe { libc::lseek(file.as_raw_fd(), offset, whence) };
+    u64::try_from(pos).map_err(|_| io::Error::last_os_error())
+}
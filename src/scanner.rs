@@ -0,0 +1,229 @@
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::os::unix::fs::MetadataExt;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use itertools::chain;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    fmap, fset, get_files_info, get_pid_name, num_name, parse_net_table, parse_unix_table, Data,
+    DirFd, FMap, FileRole, Filetype, Filter, IStr, InetSocket, NetProto, NumNameBuf, ProcInfo,
+    StatCell, UnixSocket,
+};
+
+/// Where procfs is mounted, `/proc` unless scanning a fixture or another namespace (eg. `/host/proc`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProcRoot {
+    path: IStr,
+    /// False for the root of a snapshot, its pids may have exited or been reused since
+    live: bool,
+}
+
+impl Default for ProcRoot {
+    fn default() -> Self {
+        ProcRoot::new("/proc")
+    }
+}
+
+impl Display for ProcRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl ProcRoot {
+    #[must_use]
+    pub fn new(path: &str) -> Self {
+        ProcRoot {
+            path: path.trim_end_matches('/').into(),
+            live: true,
+        }
+    }
+    /// The root a snapshot was saved from, nothing is read from it
+    #[must_use]
+    pub fn saved(path: &str) -> Self {
+        ProcRoot {
+            live: false,
+            ..ProcRoot::new(path)
+        }
+    }
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// Whether the processes can be read from this root, false for a snapshot
+    #[must_use]
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+    /// `<root>/<pid>`, the proc path taken by the `get_pid_*` parsers
+    #[must_use]
+    pub fn pid_path(&self, pid: u64) -> String {
+        format!("{}/{pid}", self.path)
+    }
+    /// [`ProcRoot::pid_path`], `None` for a snapshot
+    #[must_use]
+    pub fn live_pid_path(&self, pid: u64) -> Option<String> {
+        self.live.then(|| self.pid_path(pid))
+    }
+    /// `<root>/<rel>`
+    #[must_use]
+    pub fn join(&self, rel: &str) -> String {
+        format!("{}/{rel}", self.path)
+    }
+    /// The link in `<root>/<pid>` to the file a process uses as `role`, `None` for mappings and snapshots
+    #[must_use]
+    pub fn link_path(&self, pid: u64, role: FileRole) -> Option<String> {
+        let link = match role {
+            FileRole::Cwd => "cwd".to_owned(),
+            FileRole::Rtd => "root".to_owned(),
+            FileRole::Txt => "exe".to_owned(),
+            FileRole::Fd(fd) => format!("fd/{fd}"),
+            FileRole::Mem | FileRole::Del => return None,
+        };
+        Some(format!("{}/{link}", self.live_pid_path(pid)?))
+    }
+    /// Read one of the inet socket tables, an unreadable table is treated as empty
+    #[tracing::instrument(level = "trace")]
+    #[must_use]
+    pub fn net_table(&self, proto: NetProto) -> Vec<InetSocket> {
+        let Ok(content) = read_to_string(self.join(proto.rel_path())) else {
+            return Vec::new();
+        };
+        parse_net_table(proto, &content)
+    }
+    /// All tcp/udp sockets of the network namespace of this procfs
+    #[must_use]
+    pub fn inet_sockets(&self) -> Vec<InetSocket> {
+        NetProto::ALL
+            .into_iter()
+            .flat_map(|proto| self.net_table(proto))
+            .collect()
+    }
+    /// Read `net/unix` keyed by inode, an unreadable table is treated as empty
+    #[tracing::instrument(level = "trace")]
+    #[must_use]
+    pub fn unix_table(&self) -> FMap<u64, UnixSocket> {
+        let Ok(content) = read_to_string(self.join("net/unix")) else {
+            return fmap(0);
+        };
+        parse_unix_table(&content)
+    }
+}
+
+/// Options for a scan of procfs.
+///
+/// ```no_run
+/// # use lsof::{Filetype, Scanner};
+/// let data = Scanner::new()
+///     .proc_root("/host/proc")
+///     .filetype(Filetype::Fd)
+///     .scan()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct Scanner {
+    proc_root: ProcRoot,
+    filetype: Filetype,
+    filter: Filter,
+}
+
+impl Scanner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Scan the procfs mounted at `path` instead of `/proc`
+    #[must_use]
+    pub fn proc_root(mut self, path: &str) -> Self {
+        self.proc_root = ProcRoot::new(path);
+        self
+    }
+    /// Which of the files of each process to read
+    #[must_use]
+    pub fn filetype(mut self, filetype: Filetype) -> Self {
+        self.filetype = filetype;
+        self
+    }
+    /// Only keep the processes and files matching `filter`
+    #[must_use]
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Scan the proc root
+    ///
+    /// # Errors
+    /// anyhow: the proc root could not be listed
+    pub fn scan(&self) -> Result<Data> {
+        let Scanner {
+            proc_root,
+            filetype,
+            filter,
+        } = self;
+        let mut data = Data::new();
+        data.proc_root = proc_root.clone();
+        let strings = &*data.strings;
+        let proc_dir =
+            DirFd::open(proc_root.path()).with_context(|| format!("could not open {proc_root}"))?;
+        let pids = proc_dir
+            .numeric_entries()
+            .with_context(|| format!("could not list {proc_root}"))?;
+        data.pid_to_files = pids
+            .into_par_iter()
+            .filter(|&pid| filter.matches_pid(pid))
+            .filter_map(|pid| {
+                let proc_path_str = proc_root.pid_path(pid);
+                //get process other info
+                let name = get_pid_name(proc_path_str.clone());
+                if !filter.matches_proc(name.as_deref()) {
+                    return None;
+                }
+                // The process exited since the root was listed
+                let pid_dir = proc_dir
+                    .open_dir_at(num_name(pid, &mut NumNameBuf::default()))
+                    .ok()?;
+                let uid = pid_dir.metadata().ok().map(|m| m.uid());
+                if !filter.matches_user(uid) {
+                    return None;
+                }
+
+                let (mut records, mut fds) =
+                    get_files_info(*filetype, &proc_path_str, &pid_dir, strings);
+                if filter.filters_files() {
+                    records.retain(|r| filter.matches_file(&r.file, r.deleted));
+                    fds.retain(|f| filter.matches_file(&f.file, f.deleted));
+                    if records.is_empty() && fds.is_empty() {
+                        return None;
+                    }
+                }
+                let mut fileset = fset(records.len() + fds.len());
+                chain!(records.iter().map(|r| &r.file), fds.iter().map(|f| &f.file))
+                    .cloned()
+                    .collect_into(&mut fileset);
+
+                Some((
+                    pid,
+                    ProcInfo {
+                        name: name.map(|name| strings.intern(&name)),
+                        uid,
+                        files: fileset,
+                        fds,
+                        records,
+                        proc_root: proc_root.clone(),
+                        pid,
+                        stat: StatCell::default(),
+                        status: OnceLock::new(),
+                    },
+                ))
+            })
+            .collect();
+        if filetype.includes_socket() {
+            data.unix_sockets = proc_root.unix_table();
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{bail, ensure, Context, Result};
+use itertools::chain;
+
+use crate::{
+    fmap, fset, Data, FMap, FileRecord, FileRole, IStr, OpenFile, OpenFlags, ProcInfo, ProcRoot,
+    StatCell, UnixSocket,
+};
+
+// A snapshot is little endian:
+//   magic, version: u32
+//   strings: u32 count, then a u32 length and the bytes of each
+//   proc root: str
+//   processes: u32 count, then for each
+//     pid: u64, name: opt str, uid: opt u32
+//     records: u32 count, then role, file: str and deleted: u8 for each
+//     fds: u32 count, then fd: u32, file: str, pos: u64, flags: u32, mnt_id: opt u64, deleted: u8 for each
+//   unix sockets: u32 count, then inode: u64, path: opt str, type: u16, state: u8, flags: u32 for each
+// where a str is an index into the strings and an opt is a u8 0 or 1 followed by the value.
+// The file set of a process is the files of its records and fds, so it is not stored.
+
+/// The first bytes of a snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"LSOFSNAP";
+/// Version of the format written by [`Data::save`], bumped when the layout changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+impl Data {
+    /// Save the processes and unix sockets of this scan to `path`, to be read back with [`Data::load`]
+    ///
+    /// # Errors
+    /// anyhow: `path` could not be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("could not create snapshot {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        self.write_snapshot(&mut out)
+            .and_then(|()| out.flush())
+            .with_context(|| format!("could not write snapshot {}", path.display()))
+    }
+
+    /// Load a snapshot written by [`Data::save`].
+    /// `files_to_pid` is not constructed. Nothing is read from the live procfs, whose pids may have been reused,
+    /// so [`ProcInfo::stat`] and [`Entry::metadata`](crate::Entry::metadata) are `None`
+    ///
+    /// # Errors
+    /// anyhow: `path` could not be read, is not a snapshot or has an unsupported version
+    pub fn load(path: impl AsRef<Path>) -> Result<Data> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("could not open snapshot {}", path.display()))?;
+        Data::read_snapshot(BufReader::new(file))
+            .with_context(|| format!("could not read snapshot {}", path.display()))
+    }
+
+    /// Write the snapshot format to `out`, see [`Data::save`]
+    ///
+    /// # Errors
+    /// io: writing to `out` failed
+    pub fn write_snapshot(&self, out: impl Write) -> io::Result<()> {
+        let mut w = SnapshotWriter {
+            out,
+            index: fmap(self.strings.len()),
+        };
+        w.collect_strings(self);
+        w.out.write_all(SNAPSHOT_MAGIC)?;
+        w.u32(SNAPSHOT_VERSION)?;
+        let mut strings = w
+            .index
+            .iter()
+            .map(|(s, &i)| (i, s.clone()))
+            .collect::<Vec<_>>();
+        strings.sort_unstable_by_key(|&(i, _)| i);
+        w.len(strings.len())?;
+        for (_, s) in strings {
+            w.len(s.len())?;
+            w.out.write_all(s.as_bytes())?;
+        }
+
+        w.str(self.proc_root.path())?;
+        w.len(self.pid_to_files.len())?;
+        for (&pid, info) in &self.pid_to_files {
+            w.u64(pid)?;
+            w.opt(info.name.as_deref(), SnapshotWriter::str)?;
+            w.opt(info.uid, SnapshotWriter::u32)?;
+            w.len(info.records.len())?;
+            for record in &info.records {
+                w.role(record.role)?;
+                w.str(&record.file)?;
+                w.out.write_all(&[u8::from(record.deleted)])?;
+            }
+            w.len(info.fds.len())?;
+            for fd in &info.fds {
+                w.u32(fd.fd)?;
+                w.str(&fd.file)?;
+                w.u64(fd.pos)?;
+                w.u32(fd.flags.bits())?;
+                w.opt(fd.mnt_id, SnapshotWriter::u64)?;
+                w.out.write_all(&[u8::from(fd.deleted)])?;
+            }
+        }
+        w.len(self.unix_sockets.len())?;
+        for socket in self.unix_sockets.values() {
+            w.u64(socket.inode)?;
+            w.opt(socket.path.as_deref(), SnapshotWriter::str)?;
+            w.out.write_all(&u16::from(socket.kind).to_le_bytes())?;
+            w.out.write_all(&[socket.state.into()])?;
+            w.u32(socket.flags)?;
+        }
+        Ok(())
+    }
+
+    /// Read the snapshot format from `input`, see [`Data::load`]
+    ///
+    /// # Errors
+    /// anyhow: reading failed, or `input` is not a snapshot of a supported version
+    pub fn read_snapshot(input: impl Read) -> Result<Data> {
+        let mut data = Data::new();
+        let mut r = SnapshotReader {
+            input,
+            strings: Vec::new(),
+        };
+        let mut magic = [0; SNAPSHOT_MAGIC.len()];
+        r.input
+            .read_exact(&mut magic)
+            .context("not an lsof snapshot")?;
+        ensure!(&magic == SNAPSHOT_MAGIC, "not an lsof snapshot");
+        let version = r.u32()?;
+        if version != SNAPSHOT_VERSION {
+            bail!("snapshot version {version} is not supported, expected {SNAPSHOT_VERSION}");
+        }
+        for _ in 0..r.u32()? {
+            let s = r.string()?;
+            r.strings.push(data.strings.intern(&s));
+        }
+
+        data.proc_root = ProcRoot::saved(&r.str()?);
+        let procs = r.u32()?;
+        data.pid_to_files = fmap(procs.min(1 << 16) as usize);
+        for _ in 0..procs {
+            let pid = r.u64()?;
+            let name = r.opt(SnapshotReader::str)?;
+            let uid = r.opt(SnapshotReader::u32)?;
+            let records = (0..r.u32()?)
+                .map(|_| {
+                    Ok(FileRecord {
+                        role: r.role()?,
+                        file: r.str()?,
+                        deleted: r.deleted()?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let fds = (0..r.u32()?)
+                .map(|_| {
+                    Ok(OpenFile {
+                        fd: r.u32()?,
+                        file: r.str()?,
+                        pos: r.u64()?,
+                        flags: OpenFlags::from_bits_retain(r.u32()?),
+                        mnt_id: r.opt(SnapshotReader::u64)?,
+                        deleted: r.deleted()?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut files = fset(records.len() + fds.len());
+            chain!(records.iter().map(|r| &r.file), fds.iter().map(|f| &f.file))
+                .cloned()
+                .collect_into(&mut files);
+            let info = ProcInfo {
+                name,
+                uid,
+                files,
+                fds,
+                records,
+                proc_root: data.proc_root.clone(),
+                pid,
+                stat: StatCell::default(),
+                status: OnceLock::new(),
+            };
+            data.pid_to_files.insert(pid, info);
+        }
+        for _ in 0..r.u32()? {
+            let socket = UnixSocket {
+                inode: r.u64()?,
+                path: r.opt(SnapshotReader::str)?,
+                kind: u16::from_le_bytes(r.bytes()?).into(),
+                state: u8::from_le_bytes(r.bytes()?).into(),
+                flags: r.u32()?,
+            };
+            data.unix_sockets.insert(socket.inode, socket);
+        }
+        Ok(data)
+    }
+}
+
+struct SnapshotWriter<W> {
+    out: W,
+    /// Index of each string in the string table
+    index: FMap<IStr, u32>,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    fn collect_strings(&mut self, data: &Data) {
+        let mut add = |s: &IStr| {
+            let next = u32::try_from(self.index.len()).expect("Less than 4G strings");
+            self.index.entry(s.clone()).or_insert(next);
+        };
+        add(&data.proc_root.path().into());
+        for info in data.pid_to_files.values() {
+            info.name.iter().for_each(&mut add);
+            info.records.iter().for_each(|r| add(&r.file));
+            info.fds.iter().for_each(|f| add(&f.file));
+        }
+        for socket in data.unix_sockets.values() {
+            socket.path.iter().for_each(&mut add);
+        }
+    }
+    fn u32(&mut self, n: u32) -> io::Result<()> {
+        self.out.write_all(&n.to_le_bytes())
+    }
+    fn u64(&mut self, n: u64) -> io::Result<()> {
+        self.out.write_all(&n.to_le_bytes())
+    }
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        let len = u32::try_from(len).map_err(|_| io::Error::other("too long for a snapshot"))?;
+        self.u32(len)
+    }
+    fn str(&mut self, s: &str) -> io::Result<()> {
+        let i = self.index[s];
+        self.u32(i)
+    }
+    fn opt<T>(
+        &mut self,
+        value: Option<T>,
+        write: impl FnOnce(&mut Self, T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.out.write_all(&[u8::from(value.is_some())])?;
+        value.map_or(Ok(()), |value| write(self, value))
+    }
+    fn role(&mut self, role: FileRole) -> io::Result<()> {
+        let (tag, fd) = match role {
+            FileRole::Cwd => (0, 0),
+            FileRole::Rtd => (1, 0),
+            FileRole::Txt => (2, 0),
+            FileRole::Mem => (3, 0),
+            FileRole::Del => (4, 0),
+            FileRole::Fd(fd) => (5, fd),
+        };
+        self.out.write_all(&[tag])?;
+        self.u32(fd)
+    }
+}
+
+struct SnapshotReader<R> {
+    input: R,
+    /// The string table, already interned
+    strings: Vec<IStr>,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.input
+            .read_exact(&mut buf)
+            .context("snapshot is truncated")?;
+        Ok(buf)
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+    /// An entry of the string table
+    fn string(&mut self) -> Result<String> {
+        let len = u64::from(self.u32()?);
+        let mut s = String::new();
+        let read = (&mut self.input)
+            .take(len)
+            .read_to_string(&mut s)
+            .context("snapshot has a string that is not UTF-8")?;
+        ensure!(read as u64 == len, "snapshot is truncated");
+        Ok(s)
+    }
+    fn str(&mut self) -> Result<IStr> {
+        let i = self.u32()?;
+        self.strings
+            .get(i as usize)
+            .cloned()
+            .with_context(|| format!("snapshot refers to string {i} of {}", self.strings.len()))
+    }
+    fn opt<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        match self.bytes::<1>()? {
+            [0] => Ok(None),
+            [1] => read(self).map(Some),
+            [b] => bail!("snapshot has a bad option tag {b}"),
+        }
+    }
+    fn deleted(&mut self) -> Result<bool> {
+        match self.bytes()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [b] => bail!("snapshot has a bad deleted flag {b}"),
+        }
+    }
+    fn role(&mut self) -> Result<FileRole> {
+        let [tag] = self.bytes()?;
+        let fd = self.u32()?;
+        Ok(match tag {
+            0 => FileRole::Cwd,
+            1 => FileRole::Rtd,
+            2 => FileRole::Txt,
+            3 => FileRole::Mem,
+            4 => FileRole::Del,
+            5 => FileRole::Fd(fd),
+            tag => bail!("snapshot has a bad file role {tag}"),
+        })
+    }
+}
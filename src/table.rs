@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use std::fs::Metadata;
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+use colored::Colorize;
+use itertools::Itertools;
+
+use crate::{local_time, Change, Entry, Stat};
+
+/// A column of the table output, named like lsof's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Command,
+    Pid,
+    /// The main thread, whose tid is the pid. The other threads share its files, so they are not listed separately
+    Tid,
+    User,
+    Fd,
+    Type,
+    Device,
+    SizeOff,
+    Node,
+    Name,
+    /// The parent pid, from `/proc/<pid>/stat` like the other process columns
+    Ppid,
+    State,
+    /// When the process started, the time if it was today, otherwise the day
+    Start,
+}
+
+impl Column {
+    pub const ALL: [Column; 13] = [
+        Column::Command,
+        Column::Pid,
+        Column::Tid,
+        Column::User,
+        Column::Fd,
+        Column::Type,
+        Column::Device,
+        Column::SizeOff,
+        Column::Node,
+        Column::Name,
+        Column::Ppid,
+        Column::State,
+        Column::Start,
+    ];
+    /// The columns of lsof without `-K`
+    pub const DEFAULT: [Column; 9] = [
+        Column::Command,
+        Column::Pid,
+        Column::User,
+        Column::Fd,
+        Column::Type,
+        Column::Device,
+        Column::SizeOff,
+        Column::Node,
+        Column::Name,
+    ];
+
+    #[must_use]
+    pub const fn header(self) -> &'static str {
+        match self {
+            Column::Command => "COMMAND",
+            Column::Pid => "PID",
+            Column::Tid => "TID",
+            Column::User => "USER",
+            Column::Fd => "FD",
+            Column::Type => "TYPE",
+            Column::Device => "DEVICE",
+            Column::SizeOff => "SIZE/OFF",
+            Column::Node => "NODE",
+            Column::Name => "NAME",
+            Column::Ppid => "PPID",
+            Column::State => "STATE",
+            Column::Start => "START",
+        }
+    }
+    const fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Column::Pid | Column::Tid | Column::SizeOff | Column::Node | Column::Ppid
+        )
+    }
+    const fn needs_metadata(self) -> bool {
+        matches!(
+            self,
+            Column::Type | Column::Device | Column::SizeOff | Column::Node
+        )
+    }
+    const fn needs_stat(self) -> bool {
+        matches!(self, Column::Ppid | Column::State | Column::Start)
+    }
+    /// The cell of this column for `entry`, `meta` is the metadata of its file and `stat` of its process
+    #[must_use]
+    pub fn cell(self, entry: &Entry, meta: Option<&Metadata>, stat: Option<&Stat>) -> String {
+        match self {
+            Column::Command => entry.proc.to_string(),
+            // The files are listed for the main thread
+            Column::Pid | Column::Tid => entry.pid.to_string(),
+            Column::User => entry.user.to_string(),
+            Column::Fd => entry.fd_column(),
+            Column::Type => entry.file_type_with(meta).to_string(),
+            Column::Device => meta.map_or_else(String::new, |meta| {
+                let ty = meta.file_type();
+                let dev = if ty.is_char_device() || ty.is_block_device() {
+                    meta.rdev()
+                } else {
+                    meta.dev()
+                };
+                let (major, minor) = dev_major_minor(dev);
+                format!("{major},{minor}")
+            }),
+            Column::SizeOff => match (meta, &entry.fd) {
+                (Some(meta), _) if meta.is_file() || meta.is_dir() => meta.len().to_string(),
+                (_, Some(fd)) => format!("0t{}", fd.pos),
+                _ => String::new(),
+            },
+            Column::Node => meta.map_or_else(String::new, |meta| meta.ino().to_string()),
+            Column::Name => entry.name_column(),
+            Column::Ppid => stat.map_or_else(String::new, |stat| stat.ppid.to_string()),
+            Column::State => stat.map_or_else(String::new, |stat| stat.state().to_string()),
+            Column::Start => stat
+                .and_then(|stat| stat.start_time(&entry.proc_root))
+                .map_or_else(String::new, start_column),
+        }
+    }
+}
+
+/// Like the STIME of ps, `HH:MM` for a time today and `MmmDD` before
+fn start_column(start: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let tm = local_time(start);
+    let now = local_time(SystemTime::now());
+    if (tm.tm_year, tm.tm_yday) == (now.tm_year, now.tm_yday) {
+        format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+    } else {
+        let month = usize::try_from(tm.tm_mon).ok().and_then(|m| MONTHS.get(m));
+        let month = month.unwrap_or(&"?");
+        format!("{month}{:02}", tm.tm_mday)
+    }
+}
+
+/// Split a `dev_t` into its major and minor numbers
+#[must_use]
+pub const fn dev_major_minor(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    #[allow(clippy::cast_possible_truncation)]
+    (major as u32, minor as u32)
+}
+
+/// `--columns`, a column header or its lowercase name
+impl FromStr for Column {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(column) = Column::ALL.into_iter().find(|c| {
+            c.header().eq_ignore_ascii_case(s) || (*c == Column::SizeOff && s == "size-off")
+        }) else {
+            bail!("unknown column {s}");
+        };
+        Ok(column)
+    }
+}
+
+/// Renders entries with a header and aligned columns
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Table {
+    pub columns: Vec<Column>,
+    /// Truncate the NAME column so rows fit in this width
+    pub width: Option<usize>,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table {
+            columns: Column::DEFAULT.to_vec(),
+            width: None,
+        }
+    }
+}
+
+impl Table {
+    /// Write the header and a row for each entry, the columns where `bold` is true are bolded
+    ///
+    /// # Errors
+    /// If writing to `out` fails
+    pub fn write(
+        &self,
+        entries: &[Entry],
+        bold: impl Fn(Column) -> bool,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let rows = entries.iter().map(|entry| (entry, None));
+        self.write_rows(rows, bold, false, out)
+    }
+
+    /// Like [`Table::write`], the rows that changed since a previous scan start with `+` if added and `-` if removed,
+    /// and are also green or red
+    ///
+    /// # Errors
+    /// If writing to `out` fails
+    pub fn write_changes<'e>(
+        &self,
+        entries: impl IntoIterator<Item = (&'e Entry, Option<Change>)>,
+        bold: impl Fn(Column) -> bool,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        self.write_rows(entries, bold, true, out)
+    }
+
+    fn write_rows<'e>(
+        &self,
+        entries: impl IntoIterator<Item = (&'e Entry, Option<Change>)>,
+        bold: impl Fn(Column) -> bool,
+        marks: bool,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let rows = entries
+            .into_iter()
+            .map(|(entry, change)| (self.cells(entry), change))
+            .collect_vec();
+        let mut widths = self.columns.iter().map(|c| c.header().len()).collect_vec();
+        for (row, _) in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        if let (Some(max), Some(name)) = (
+            self.width,
+            self.columns.iter().position(|&c| c == Column::Name),
+        ) {
+            let marks = if marks { 2 } else { 0 };
+            let others: usize =
+                widths.iter().sum::<usize>() - widths[name] + widths.len() - 1 + marks;
+            let fits = max.saturating_sub(others).max(Column::Name.header().len());
+            widths[name] = widths[name].min(fits);
+        }
+
+        let header = self.columns.iter().map(|c| c.header().to_string());
+        if marks {
+            write!(out, "  ")?;
+        }
+        self.write_row(header.collect(), &widths, |_| false, None, out)?;
+        for (row, change) in rows {
+            if marks {
+                let mark = match change {
+                    Some(Change::Added) => "+".green(),
+                    Some(Change::Removed) => "-".red(),
+                    None => " ".normal(),
+                };
+                write!(out, "{mark} ")?;
+            }
+            self.write_row(row, &widths, &bold, change, out)?;
+        }
+        Ok(())
+    }
+
+    /// The cells of `entry` in each of the columns
+    #[must_use]
+    pub fn cells(&self, entry: &Entry) -> Vec<String> {
+        let needs_metadata = self.columns.iter().any(|c| c.needs_metadata());
+        let meta = needs_metadata.then(|| entry.metadata()).flatten();
+        let needs_stat = self.columns.iter().any(|c| c.needs_stat());
+        let stat = needs_stat.then(|| entry.stat()).flatten();
+        self.columns
+            .iter()
+            .map(|c| c.cell(entry, meta.as_ref(), stat))
+            .collect()
+    }
+
+    fn write_row(
+        &self,
+        row: Vec<String>,
+        widths: &[usize],
+        bold: impl Fn(Column) -> bool,
+        change: Option<Change>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let last = self.columns.len() - 1;
+        for (i, (cell, (&column, &width))) in row
+            .into_iter()
+            .zip(self.columns.iter().zip(widths))
+            .enumerate()
+        {
+            let cell = truncate(cell, width);
+            let cell = if column.is_numeric() {
+                format!("{cell:>width$}")
+            } else if i == last {
+                cell
+            } else {
+                format!("{cell:<width$}")
+            };
+            let sep = if i == last { "\n" } else { " " };
+            let cell = if bold(column) {
+                cell.bold()
+            } else {
+                cell.normal()
+            };
+            let cell = match change {
+                Some(Change::Added) => cell.green(),
+                Some(Change::Removed) => cell.red(),
+                None => cell,
+            };
+            write!(out, "{cell}{sep}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A cell of `--format csv`, quoted if it has a comma, quote or line break
+#[must_use]
+pub fn csv_cell(cell: &str) -> Cow<'_, str> {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\"")).into()
+    } else {
+        cell.into()
+    }
+}
+
+/// A cell of `--format tsv`, with backslashes, tabs and line breaks escaped
+#[must_use]
+pub fn tsv_cell(cell: &str) -> Cow<'_, str> {
+    if cell.contains(['\\', '\t', '\n', '\r']) {
+        cell.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .into()
+    } else {
+        cell.into()
+    }
+}
+
+/// Cut `cell` down to `width` characters, ending it with `…` if anything was cut
+fn truncate(cell: String, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell;
+    }
+    let mut cell = cell
+        .chars()
+        .take(width.saturating_sub(1))
+        .collect::<String>();
+    cell.push('…');
+    cell
+}
@@ -1,18 +1,884 @@
 use super::*;
+use fixture::ProcFixture;
+use regex::Regex;
 // TODO: test coverage
 
+/// init, two nginx workers sharing a log and a bash with a pipe and a tcp socket
+fn fixture() -> ProcFixture {
+    let proc = ProcFixture::new();
+    proc.process(1, "init")
+        .cwd("/")
+        .root("/")
+        .exe("/sbin/init")
+        .fd(0, "/dev/null")
+        .map("/usr/lib/libc.so.6");
+    for pid in [42, 43] {
+        let nginx = proc.process(pid, "nginx");
+        nginx
+            .cwd("/srv")
+            .root("/")
+            .exe("/usr/sbin/nginx")
+            .fd_with(4, "/var/log/access.log", 10, 0o2001)
+            .map("/usr/lib/libc.so.6");
+        if pid == 42 {
+            nginx.fd(0, "/dev/null").fd(3, "socket:[2709]");
+        }
+    }
+    proc.process(100, "bash")
+        .cwd("/root")
+        .fd(0, "pipe:[555]")
+        .fd(1, "socket:[8080]");
+    proc.net(
+        "unix",
+        "00000000846603c9: 00000002 00000000 00010000 0001 01  2709 /run/nginx.sock",
+    );
+    proc.net("tcp", "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 8080 1 0000000000000000 100 0 0 10 0");
+    proc
+}
+
+fn scan(proc: &ProcFixture) -> Data {
+    Scanner::new().proc_root(proc.root()).scan().unwrap()
+}
+
+fn pids(procs: Vec<Result<Proc, u64>>) -> Vec<u64> {
+    procs.into_iter().map(|p| p.unwrap().pid).sorted().collect()
+}
+
 #[test]
 fn test_lsall() {
-    let result = lsof().unwrap();
-    println!("{:?}", result);
+    let proc = fixture();
+    let data = Scanner::new()
+        .proc_root(proc.root())
+        .filetype(Filetype::All)
+        .scan()
+        .unwrap();
+    let entries = data.flattened().collect_vec();
+    assert_eq!(
+        entries.iter().map(|e| e.pid).dedup().sorted().collect_vec(),
+        [1, 42, 43, 100]
+    );
+    assert_eq!(entries.len(), 20);
 }
 
 #[test]
-fn test_target() {
-    let filepath = "/usr/lib64/librt-2.28.so".to_owned();
-    let result = lsof_file(filepath).unwrap();
-    // println!("{:?}", result);
-    for r in result {
-        println!("pid:{}  ,name: {:?} \n", r.pid, r.name)
-    }
+fn test_fixture_scan() {
+    let proc = fixture();
+    let data = scan(&proc);
+    let procs = data.pid_to_files();
+    assert_eq!(
+        procs.keys().copied().sorted().collect_vec(),
+        [1, 42, 43, 100]
+    );
+    assert_eq!(procs[&42].name.as_deref(), Some("nginx"));
+    assert_eq!(procs[&42].files.len(), 7);
+    let log = procs[&42].fds.iter().find(|f| f.fd == 4).unwrap();
+    assert_eq!(&*log.file, "/var/log/access.log");
+    assert_eq!(log.pos, 10);
+    assert_eq!(log.fd_column(), "4w");
+    assert!(procs[&1].records.contains(&FileRecord {
+        role: FileRole::Txt,
+        file: "/sbin/init".into(),
+        deleted: false,
+    }));
+    assert_eq!(
+        data.unix_sockets()[&2709].path.as_deref(),
+        Some("/run/nginx.sock")
+    );
+    assert_eq!(procs[&1].stat().unwrap().comm, "init");
+    // The boot time of the fixture, not of this machine
+    let stat = procs[&1].stat().unwrap();
+    let boot = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    assert_eq!(
+        stat.start_time(data.proc_root()),
+        Some(boot + stat.starttime_since_boot())
+    );
+    assert_eq!(procs[&100].status().unwrap().name, "bash");
+
+    let data = Scanner::new()
+        .proc_root(proc.root())
+        .filetype(Filetype::Cwd)
+        .filter(Filter {
+            proc_regex: Some(Regex::new("^ng").unwrap()),
+            ..Filter::default()
+        })
+        .scan()
+        .unwrap();
+    let procs = data.pid_to_files();
+    assert_eq!(procs.keys().copied().sorted().collect_vec(), [42, 43]);
+    assert_eq!(
+        procs[&43].files.iter().map(|f| &**f).collect_vec(),
+        ["/srv"]
+    );
+
+    // The entries of a process share its stat, read once
+    let nginx = scan(&proc)
+        .flattened()
+        .filter(|e| e.pid == 42)
+        .collect_vec();
+    assert_eq!(nginx[0].stat().unwrap().comm, "nginx");
+    fs::remove_file(format!("{}/42/stat", proc.root())).unwrap();
+    assert!(nginx.iter().all(|e| e.stat().is_some()));
+}
+
+#[test]
+fn test_find() {
+    let proc = fixture();
+    let mut data = scan(&proc);
+    assert!(data.find("/dev/null").is_err());
+    data.invert_pid_to_files("");
+    assert_eq!(pids(data.find("/dev/null").unwrap()), [1, 42]);
+    assert_eq!(pids(data.find("/var/log/access.log").unwrap()), [42, 43]);
+    // Bound unix sockets are found by their path
+    assert_eq!(pids(data.find("/run/nginx.sock").unwrap()), [42]);
+    assert_eq!(pids(data.find_port(8080).unwrap()), [100]);
+    assert!(data.find("/nonexistent").is_err());
+    assert!(data.find_port(22).is_err());
+
+    let mut data = scan(&proc);
+    data.invert_pid_to_files("/usr/lib/libc.so.6");
+    let files_to_pid = data.files_to_pid().unwrap();
+    assert_eq!(files_to_pid.len(), 1);
+    assert_eq!(
+        files_to_pid["/usr/lib/libc.so.6"]
+            .pids
+            .iter()
+            .copied()
+            .sorted()
+            .collect_vec(),
+        [1, 42, 43]
+    );
+    // 11 files and the path of the bound socket
+    assert_eq!(data.into_files_to_pid().len(), 12);
+
+    let mut data = scan(&proc);
+    data.retain_file("/run/nginx.sock");
+    assert_eq!(data.pid_to_files().keys().copied().collect_vec(), [42]);
+    assert_eq!(
+        data.pid_to_files()[&42].fds[0].file.as_ref(),
+        "socket:[2709]"
+    );
+    data.retain_file("/nonexistent");
+    assert!(data.pid_to_files().is_empty());
+}
+
+#[test]
+fn test_into_proc_to_files() {
+    let proc = fixture();
+    let procs = scan(&proc).into_proc_to_files();
+    assert_eq!(
+        procs.keys().map(|p| &**p).sorted().collect_vec(),
+        ["bash", "init", "nginx"]
+    );
+    let (pids, files) = &procs["nginx"];
+    assert_eq!(pids.iter().copied().sorted().collect_vec(), [42, 43]);
+    assert_eq!(
+        files.iter().map(|f| &**f).sorted().collect_vec(),
+        [
+            "/",
+            "/dev/null",
+            "/srv",
+            "/usr/lib/libc.so.6",
+            "/usr/sbin/nginx",
+            "/var/log/access.log",
+            "socket:[2709]",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_net_table() {
+    let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 662 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:B1B2 01 00000000:00000000 00:00000000 00000000  1000        0 2780 2 0000000000000000 20 4 0 18 -1
+";
+    let sockets = parse_net_table(NetProto::Tcp, tcp);
+    assert_eq!(sockets.len(), 2);
+    assert_eq!(sockets[0].local, "127.0.0.1:8080".parse().unwrap());
+    assert_eq!(sockets[0].state, SocketState::Listen);
+    assert_eq!(sockets[0].uid, 1000);
+    assert_eq!(sockets[0].inode, 662);
+    assert_eq!(sockets[1].remote, "127.0.0.1:45490".parse().unwrap());
+    assert!(sockets[1].has_port(8080));
+    assert!(sockets[1].has_port(45490));
+    // Listening, not connected to port 0
+    assert!(!sockets[0].has_port(0));
+
+    let tcp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:0016 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 3021 1 0000000000000000 100 0 0 10 0
+";
+    let sockets = parse_net_table(NetProto::Tcp6, tcp6);
+    assert_eq!(sockets[0].local, "[::1]:22".parse().unwrap());
+    assert_eq!(socket_inode(&sockets[0].fd_link()), Some(3021));
+}
+
+#[test]
+fn test_parse_unix_table() {
+    let unix = "Num       RefCount Protocol Flags    Type St Inode Path
+0000000023d37d58: 00000003 00000000 00000000 0001 03   658
+00000000846603c9: 00000002 00000000 00010000 0001 01  2709 /run/docker.sock
+00000000846603ca: 00000002 00000000 00000000 0002 01  2710 @/tmp/.X11-unix/X0
+00000000846603cb: 00000002 00000000 00000000 0005 01  2711 /tmp/with space.sock
+";
+    let sockets = parse_unix_table(unix);
+    assert_eq!(sockets.len(), 4);
+    assert_eq!(sockets[&658].path, None);
+    assert_eq!(sockets[&658].state, UnixSocketState::Connected);
+    assert_eq!(sockets[&2709].path.as_deref(), Some("/run/docker.sock"));
+    assert!(sockets[&2709].is_listening());
+    assert_eq!(sockets[&2709].kind, UnixSocketType::Stream);
+    assert_eq!(sockets[&2710].path.as_deref(), Some("@/tmp/.X11-unix/X0"));
+    assert_eq!(sockets[&2710].kind, UnixSocketType::Dgram);
+    assert_eq!(sockets[&2711].path.as_deref(), Some("/tmp/with space.sock"));
+}
+
+#[test]
+fn test_parse_fdinfo() {
+    let mut open_file = OpenFile {
+        fd: 4,
+        ..OpenFile::default()
+    };
+    parse_fdinfo(
+        "pos:\t1024\nflags:\t02102001\nmnt_id:\t25\nino:\t3\n",
+        &mut open_file,
+    );
+    assert_eq!(open_file.pos, 1024);
+    assert_eq!(open_file.mnt_id, Some(25));
+    assert!(open_file
+        .flags
+        .contains(OpenFlags::APPEND | OpenFlags::CLOEXEC));
+    assert_eq!(open_file.access_mode(), AccessMode::Write);
+    assert_eq!(open_file.fd_column(), "4w");
+}
+
+#[test]
+fn test_filetype_roles() {
+    let deleted: Filetype = "del".parse().unwrap();
+    assert!(deleted.includes_role(FileRole::Del));
+    assert!(!deleted.includes_role(FileRole::Mem));
+    assert!(!deleted.includes_role(FileRole::Fd(3)));
+    let fd: Filetype = "fd".parse().unwrap();
+    assert!(fd.includes_role(FileRole::Fd(3)));
+    assert!(!fd.includes_role(FileRole::Cwd));
+    assert_eq!(FileRole::Fd(3).to_string(), "3");
+    assert_eq!(FileRole::Rtd.to_string(), "rtd");
+}
+
+#[test]
+fn test_parse_maps() {
+    let maps = "\
+5611971a6000-5611971a8000 r--p 00000000 fe:00 317783                     /usr/bin/head
+5611971a8000-5611971ae000 r-xp 00002000 fe:00 317783                     /usr/bin/head
+7f0000000000-7f0000001000 rw-s 00000000 00:05 1234                       /dev/shm/my file (deleted)
+7ffd00000000-7ffd00021000 rw-p 00000000 00:00 0                          [stack]
+7ffd00030000-7ffd00031000 rw-p 00000000 00:00 0 
+";
+    let maps = parse_maps(maps);
+    assert_eq!(maps.len(), 5);
+    assert_eq!(maps[1].size(), 0x6000);
+    assert!(maps[1].is_executable());
+    assert_eq!(maps[1].perms.to_string(), "r-xp");
+    assert_eq!(maps[1].offset, 0x2000);
+    assert_eq!(maps[1].dev, (0xfe, 0));
+    assert_eq!(maps[2].path.as_deref(), Some("/dev/shm/my file"));
+    assert!(maps[2].deleted);
+    assert_eq!(maps[2].perms.to_string(), "rw-s");
+    assert!(!maps[3].is_file());
+    assert_eq!(maps[4].path, None);
+
+    let files = mapped_files(&maps);
+    assert_eq!(files.len(), 2);
+    assert_eq!(files["/usr/bin/head"].size, 0x8000);
+    assert_eq!(
+        files["/usr/bin/head"].perms,
+        MapPerms::READ | MapPerms::EXEC
+    );
+}
+
+#[test]
+fn test_parse_mountinfo() {
+    let mountinfo = "\
+22 1 254:0 / / rw,relatime shared:1 - ext4 /dev/vda rw
+35 22 254:0 /srv/www /var/www rw,relatime - ext4 /dev/vda rw
+40 22 0:45 / /mnt/my\\040disk rw master:2 - tmpfs tmpfs rw,size=1024k
+bad line
+";
+    let mounts = parse_mountinfo(mountinfo);
+    assert_eq!(mounts.len(), 3);
+    assert_eq!(mounts[0].mnt_id, 22);
+    assert_eq!(mounts[0].dev, (254, 0));
+    assert_eq!(mounts[1].root, "/srv/www");
+    assert_eq!(mounts[1].mount_point, "/var/www");
+    assert_eq!(mounts[2].mount_point, "/mnt/my disk");
+    assert_eq!(mounts[2].fs_type, "tmpfs");
+    assert_eq!(mounts[2].source, "tmpfs");
+    assert_eq!(split_deleted("/tmp/a (deleted)"), ("/tmp/a", true));
+    assert_eq!(split_deleted("/tmp/a"), ("/tmp/a", false));
+}
+
+#[test]
+fn test_parse_stat() {
+    let stat = "1234 (my (weird) proc) S 1 1234 1234 34816 1234 4194560 100 0 0 0 5 3 0 0 20 0 1 0 250 10000 200 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 2 0 0 0 0 0";
+    let stat: Stat = stat.parse().unwrap();
+    assert_eq!(stat.pid, 1234);
+    assert_eq!(stat.comm, "my (weird) proc");
+    assert_eq!(stat.state(), ProcState::Sleeping);
+    assert_eq!(stat.ppid, 1);
+    assert_eq!(stat.tty_nr(), Some((136, 0)));
+    assert_eq!(stat.starttime, 250);
+    assert_eq!(stat.exit_signal, Some(17));
+    assert_eq!(stat.cguest_time, Some(0));
+    // Older kernels don't have the trailing fields
+    assert_eq!(stat.start_data, None);
+    assert_eq!(stat.exit_code, None);
+
+    assert!("1234 (truncated) S 1".parse::<Stat>().is_err());
+}
+
+#[test]
+fn test_parse_status() {
+    let status = "Name:\tnginx: worker
+Umask:\t0022
+State:\tS (sleeping)
+Tgid:\t4242
+Pid:\t4242
+PPid:\t1
+TracerPid:\t0
+Uid:\t33\t33\t33\t33
+Gid:\t33\t33\t33\t34
+FDSize:\t64
+Groups:\t4 33 
+NStgid:\t4242\t7
+NSpid:\t4242\t7
+VmPeak:\t    2640 kB
+VmRSS:\t    1440 kB
+Threads:\t3
+SigBlk:\t0000000000010000
+CapEff:\t000001fffeffffff
+CapAmb:\t0000000000000000
+";
+    let status: Status = status.parse().unwrap();
+    assert_eq!(status.name, "nginx: worker");
+    assert_eq!(status.umask, Some(0o22));
+    assert_eq!(status.state, 'S');
+    assert_eq!(status.ppid, 1);
+    assert_eq!(status.uid.effective, 33);
+    assert_eq!(status.gid.fs, 34);
+    assert_eq!(status.groups, [4, 33]);
+    assert_eq!(status.ns_pid, [4242, 7]);
+    assert_eq!(status.vm_rss, Some(1440 * 1024));
+    assert_eq!(status.vm_swap, None);
+    assert_eq!(status.threads, 3);
+    assert_eq!(status.sig_blk, 1 << 16);
+    assert_eq!(status.cap_eff, 0x1ff_feff_ffff);
+    assert_eq!(status.cap_amb, Some(0));
+
+    // Kernel threads have no memory lines
+    let status: Status = "Name:\tkthreadd\nGroups:\t\n".parse().unwrap();
+    assert!(status.groups.is_empty());
+    assert_eq!(status.vm_rss, None);
+}
+
+#[test]
+fn test_parse_passwd() {
+    let users = parse_passwd(
+        "# comment\nroot:x:0:0:root:/root:/bin/bash\nwww-data:x:33:33::/var/www:/usr/sbin/nologin\ntoor:x:0:0::/:/bin/sh\n",
+    );
+    assert_eq!(users.len(), 2);
+    assert_eq!(&*users[&0], "root");
+    assert_eq!(&*users[&33], "www-data");
+
+    let filter: UserFilter = "33,^1000".parse().unwrap();
+    assert_eq!(filter.include, [33]);
+    assert_eq!(filter.exclude, [1000]);
+    assert!(filter.matches(Some(33)));
+    assert!(!filter.matches(Some(1000)));
+    assert!(!filter.matches(Some(0)));
+    let filter: UserFilter = "^1000".parse().unwrap();
+    assert!(filter.matches(Some(0)));
+    assert!(!filter.matches(Some(1000)));
+}
+
+#[test]
+fn test_filter() {
+    let filter = Filter {
+        proc_regex: Some(regex::Regex::new("^ngin").unwrap()),
+        file_regex: Some(regex::Regex::new(r"\.log$").unwrap()),
+        ..Filter::default()
+    };
+    assert!(!filter.is_empty());
+    assert!(filter.matches_pid(1));
+    assert!(filter.matches_proc(Some("nginx")));
+    assert!(!filter.matches_proc(Some("apache")));
+    assert!(!filter.matches_proc(None));
+    assert!(filter.matches_file("/var/log/access.log", false));
+    assert!(!filter.matches_file("/var/log/access.log.1", false));
+    assert!(Filter::default().is_empty());
+}
+
+#[test]
+fn test_file_kind() {
+    assert_eq!(file_kind("socket:[1234]"), "socket");
+    assert_eq!(file_kind("pipe:[1234]"), "pipe");
+    assert_eq!(file_kind("anon_inode:[eventpoll]"), "anon_inode");
+    assert_eq!(file_kind("anon_inode:inotify"), "anon_inode");
+    assert_eq!(file_kind("/dev/null"), "dev");
+    assert_eq!(file_kind("[heap]"), "[heap]");
+    assert_eq!(file_kind("/var/log/syslog.log"), ".log");
+    assert_eq!(file_kind("/home/a.b/file"), "<none>");
+    assert_eq!(file_kind("/home/user/.bashrc"), "<none>");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serialize_entry() {
+    let entry = Entry {
+        pid: 42,
+        proc: "nginx".into(),
+        uid: Some(33),
+        user: "www-data".into(),
+        file: "/var/log/access.log".into(),
+        socket_path: None,
+        role: FileRole::Fd(4),
+        deleted: false,
+        fd: Some(OpenFile {
+            fd: 4,
+            file: "/var/log/access.log".into(),
+            pos: 10,
+            flags: OpenFlags::WRONLY | OpenFlags::APPEND,
+            mnt_id: None,
+            deleted: false,
+        }),
+        proc_root: ProcRoot::default(),
+        stat: StatCell::default(),
+    };
+    let json = serde_json::to_value(&entry).unwrap();
+    assert_eq!(json["pid"], 42);
+    assert_eq!(json["user"], "www-data");
+    assert_eq!(json["role"]["Fd"], 4);
+    assert_eq!(json["fd"]["pos"], 10);
+    assert_eq!(json["fd"]["flags"], "WRONLY | APPEND");
+}
+
+#[test]
+fn test_fields() {
+    assert_eq!(
+        "pcn".parse::<Fields>().unwrap(),
+        Fields::PID | Fields::COMMAND | Fields::FD | Fields::NAME
+    );
+    assert_eq!(
+        "".parse::<Fields>().unwrap(),
+        Fields::PROCESS | Fields::FILE
+    );
+    assert_eq!("p".parse::<Fields>().unwrap(), Fields::PID);
+    assert_eq!(
+        "0".parse::<Fields>().unwrap(),
+        Fields::PROCESS | Fields::FILE | Fields::NUL
+    );
+    assert!("pX".parse::<Fields>().is_err());
+
+    let mut data = Data::new();
+    data.pid_to_files.insert(
+        u64::from(u32::MAX),
+        ProcInfo {
+            name: Some("nginx".into()),
+            fds: vec![OpenFile {
+                fd: 4,
+                file: "/var/log/access.log".into(),
+                ..OpenFile::default()
+            }],
+            records: vec![FileRecord {
+                role: FileRole::Cwd,
+                file: "/srv".into(),
+                deleted: false,
+            }],
+            ..ProcInfo::default()
+        },
+    );
+    let mut out = Vec::new();
+    data.write_fields("pcfn".parse().unwrap(), &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "p4294967295\ncnginx\nfcwd\nn/srv\nf4\nn/var/log/access.log\n"
+    );
+}
+
+#[test]
+fn test_record_cells() {
+    assert_eq!(csv_cell("/var/log/a.log"), "/var/log/a.log");
+    assert_eq!(csv_cell("a,b"), "\"a,b\"");
+    assert_eq!(csv_cell("a\"b"), "\"a\"\"b\"");
+    assert_eq!(csv_cell("a\nb"), "\"a\nb\"");
+    assert_eq!(csv_cell("a\tb"), "a\tb");
+    assert_eq!(tsv_cell("a\tb"), "a\\tb");
+    assert_eq!(tsv_cell("a\nb\\c"), "a\\nb\\\\c");
+    assert_eq!(tsv_cell("a,\"b"), "a,\"b");
+}
+
+#[test]
+fn test_table() {
+    let entry = |pid, proc: &str, file: &str| Entry {
+        pid,
+        proc: proc.into(),
+        uid: None,
+        user: "?".into(),
+        file: file.into(),
+        socket_path: None,
+        role: FileRole::Cwd,
+        deleted: false,
+        fd: None,
+        proc_root: ProcRoot::default(),
+        stat: StatCell::default(),
+    };
+    let entries = [
+        entry(1, "init", "/"),
+        entry(4242, "nginx", "/var/log/nginx/access.log"),
+    ];
+    let table = Table {
+        columns: "command,PID,fd,name"
+            .split(',')
+            .map(|c| c.parse().unwrap())
+            .collect(),
+        width: Some(25),
+    };
+    let mut out = Vec::new();
+    table.write(&entries, |_| false, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "COMMAND  PID FD  NAME\n\
+         init       1 cwd /\n\
+         nginx   4242 cwd /var/lo…\n"
+    );
+
+    // The changes are marked without colors too, eg. when piped
+    colored::control::set_override(false);
+    let removed = entry(7, "sleep", "/tmp");
+    let changes = [
+        (&entries[0], None),
+        (&entries[1], Some(Change::Added)),
+        (&removed, Some(Change::Removed)),
+    ];
+    let mut out = Vec::new();
+    table.write_changes(changes, |_| false, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "  COMMAND  PID FD  NAME\n  \
+         init       1 cwd /\n\
+         + nginx   4242 cwd /var/…\n\
+         - sleep      7 cwd /tmp\n"
+    );
+    assert_eq!(dev_major_minor(0x0803), (8, 3));
+    assert!("bogus".parse::<Column>().is_err());
+}
+
+#[test]
+fn test_interner() {
+    use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+    let strings = Interner::default();
+    let a = strings.intern("/usr/lib/libc.so.6");
+    let b = strings.intern(&String::from("/usr/lib/libc.so.6"));
+    assert!(std::sync::Arc::ptr_eq(&a, &b));
+    strings.intern("/dev/null");
+    assert_eq!(strings.len(), 2);
+    // Interned from the scan workers
+    let libs = (0..64)
+        .into_par_iter()
+        .map(|i| strings.intern(&format!("/usr/lib/lib{}.so", i % 8)))
+        .collect::<Vec<_>>();
+    assert!(std::sync::Arc::ptr_eq(&libs[1], &libs[9]));
+    assert_eq!(strings.len(), 10);
+    drop(strings);
+    // The strings outlive the interner through their handles
+    assert_eq!(&*a, "/usr/lib/libc.so.6");
+}
+
+#[test]
+fn test_dirfd() {
+    let mut buf = NumNameBuf::default();
+    assert_eq!(num_name(0, &mut buf), c"0");
+    assert_eq!(num_name(4096, &mut buf), c"4096");
+    assert_eq!(num_name(u64::MAX, &mut buf), c"18446744073709551615");
+
+    let proc = ProcFixture::new();
+    proc.process(42, "nginx")
+        .fd(0, "/dev/null")
+        .fd(4, "/var/log/access.log");
+    let fd_dir = DirFd::open(&format!("{}/42/fd", proc.root())).unwrap();
+    assert_eq!(
+        fd_dir
+            .numeric_entries()
+            .unwrap()
+            .into_iter()
+            .sorted()
+            .collect_vec(),
+        [0, 4]
+    );
+    let mut link = [0; 4096];
+    let link = fd_dir
+        .read_link_at(num_name(4, &mut buf), &mut link)
+        .unwrap();
+    assert_eq!(link, b"/var/log/access.log");
+}
+
+#[test]
+fn test_proc_root() {
+    let proc = ProcFixture::new();
+    proc.process(42, "fake").cwd("/srv").fd(0, "/dev/null");
+
+    let data = Scanner::new()
+        .proc_root(proc.root())
+        .filetype(Filetype::All)
+        .scan()
+        .unwrap();
+    assert_eq!(data.proc_root().path(), proc.root());
+    let procs = data.pid_to_files();
+    assert_eq!(procs.keys().collect_vec(), [&42]);
+    let info = &procs[&42];
+    assert_eq!(info.name.as_deref(), Some("fake"));
+    assert_eq!(
+        info.files.iter().map(|f| &**f).sorted().collect_vec(),
+        ["/dev/null", "/srv"]
+    );
+}
+
+#[test]
+fn test_diff_entries() {
+    let proc = fixture();
+    let old = scan(&proc).flattened().collect_vec();
+    let bash = proc.process(100, "bash");
+    fs::remove_file(bash.path().join("fd/1")).unwrap();
+    bash.fd(5, "/tmp/new.log");
+    let new = scan(&proc).flattened().collect_vec();
+
+    let changes = diff_entries(&old, &new);
+    let changes = changes
+        .iter()
+        .map(|(change, e)| (*change, e.pid, e.fd_column(), &*e.file))
+        .collect_vec();
+    assert_eq!(
+        changes,
+        [
+            (Change::Added, 100, "5r".to_owned(), "/tmp/new.log"),
+            (Change::Removed, 100, "1r".to_owned(), "socket:[8080]"),
+        ]
+    );
+    assert!(diff_entries(&new, &new).is_empty());
+}
+
+#[test]
+fn test_snapshot() {
+    let proc = fixture();
+    let data = scan(&proc);
+    let mut snapshot = Vec::new();
+    data.write_snapshot(&mut snapshot).unwrap();
+    assert!(snapshot.starts_with(SNAPSHOT_MAGIC));
+
+    let loaded = Data::read_snapshot(&snapshot[..]).unwrap();
+    assert_eq!(loaded.proc_root().path(), data.proc_root().path());
+    // The pids may have been reused since, so the live procfs is not read
+    assert!(!loaded.proc_root().is_live());
+    assert!(data.pid_to_files()[&1].stat().is_some());
+    assert!(loaded.pid_to_files()[&1].stat().is_none());
+    let null = |data: Data| data.flattened().find(|e| &*e.file == "/dev/null").unwrap();
+    assert!(null(data.clone()).metadata().is_some());
+    assert!(null(loaded.clone()).metadata().is_none());
+    let entries = |data: Data| {
+        data.flattened()
+            .map(|e| {
+                let fd = e.fd.as_ref().map(|f| (f.pos, f.flags.bits(), f.mnt_id));
+                (e.pid, e.proc.clone(), e.fd_column(), e.file.clone(), fd)
+            })
+            .sorted()
+            .collect_vec()
+    };
+    assert_eq!(entries(loaded.clone()), entries(data.clone()));
+    assert_eq!(loaded.unix_sockets(), data.unix_sockets());
+    let procs = loaded.pid_to_files();
+    assert_eq!(procs[&42].files, data.pid_to_files()[&42].files);
+    // Strings are shared again after loading
+    let libc = |pid| procs[&pid].files.get("/usr/lib/libc.so.6").unwrap();
+    assert!(Arc::ptr_eq(libc(1), libc(43)));
+
+    assert!(Data::read_snapshot(&b"garbage"[..]).is_err());
+    let mut other = snapshot.clone();
+    other[SNAPSHOT_MAGIC.len()..][..4].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+    let err = Data::read_snapshot(&other[..]).unwrap_err();
+    assert!(err.to_string().contains("not supported"), "{err}");
+    other[SNAPSHOT_MAGIC.len()..][..4].copy_from_slice(&(SNAPSHOT_VERSION - 1).to_le_bytes());
+    assert!(Data::read_snapshot(&other[..]).is_err());
+    assert!(Data::read_snapshot(&snapshot[..snapshot.len() - 1]).is_err());
+}
+
+#[test]
+fn test_data_diff() {
+    let proc = fixture();
+    let mut old = scan(&proc);
+    let bash = proc.process(100, "bash");
+    fs::remove_file(bash.path().join("fd/1")).unwrap();
+    bash.fd(5, "/tmp/new.log");
+    fs::remove_dir_all(proc.process(43, "nginx").path()).unwrap();
+    proc.process(200, "sleep").cwd("/tmp");
+    let mut new = scan(&proc);
+    assert!(old.diff(&new).is_err());
+    old.invert_pid_to_files("");
+    new.invert_pid_to_files("");
+
+    let changes = old.diff(&new).unwrap();
+    let changes = changes
+        .iter()
+        .map(|c| (c.pid, c.change, c.opened.len(), c.closed.clone()))
+        .collect_vec();
+    assert_eq!(
+        changes,
+        [
+            (43, Some(Change::Removed), 0, changes[0].3.clone()),
+            (100, None, 1, vec!["socket:[8080]".into()]),
+            (200, Some(Change::Added), 1, vec![]),
+        ]
+    );
+    assert_eq!(changes[0].3.len(), 5);
+    let bash = &old.diff(&new).unwrap()[1];
+    assert_eq!(&*bash.opened[0], "/tmp/new.log");
+    assert!(new.diff(&new).unwrap().is_empty());
+}
+
+#[test]
+fn test_deleted() {
+    use std::os::unix::fs::MetadataExt;
+
+    let proc = fixture();
+    // The kernel's link to an unlinked file, here a real file so it can be stat'ed
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log (deleted)");
+    fs::write(&log, vec![b'x'; 10_000]).unwrap();
+    let log = log.to_str().unwrap();
+    proc.process(300, "app").cwd("/tmp").fd(1, log).fd(2, log);
+    proc.process(301, "tail").fd(3, log);
+
+    let data = scan(&proc);
+    let fds = &data.pid_to_files()[&300].fds;
+    assert!(fds.iter().all(|f| f.deleted));
+    assert_eq!(&*fds[0].file, log.strip_suffix(" (deleted)").unwrap());
+    assert!(!data.pid_to_files()[&300].records[0].deleted);
+    let entry = data
+        .clone()
+        .flattened()
+        .find(|e| e.pid == 301 && e.fd.is_some())
+        .unwrap();
+    assert_eq!(entry.name_column(), log);
+
+    let deleted = Scanner::new()
+        .proc_root(proc.root())
+        .filter(Filter {
+            deleted: true,
+            ..Filter::default()
+        })
+        .scan()
+        .unwrap();
+    assert_eq!(
+        deleted
+            .pid_to_files()
+            .keys()
+            .copied()
+            .sorted()
+            .collect_vec(),
+        [300, 301]
+    );
+    assert_eq!(deleted.pid_to_files()[&300].files.len(), 1);
+
+    let bytes = fs::metadata(log).unwrap().blocks() * 512;
+    let report = data.pinned();
+    assert_eq!(report.files.len(), 3);
+    // The file is counted once per filesystem and once per process
+    assert_eq!(report.filesystems.len(), 1);
+    assert_eq!(report.filesystems[0].files, 1);
+    assert_eq!(report.filesystems[0].bytes, bytes);
+    let procs = report
+        .procs
+        .iter()
+        .map(|p| (p.pid, p.files, p.bytes))
+        .sorted()
+        .collect_vec();
+    assert_eq!(procs, [(300, 1, bytes), (301, 1, bytes)]);
+}
+
+#[test]
+fn test_recover() {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let proc = fixture();
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("app.db (deleted)");
+    let mut file = fs::File::create(&db).unwrap();
+    file.write_all(b"header").unwrap();
+    file.seek(SeekFrom::Start(1 << 20)).unwrap();
+    file.write_all(b"page").unwrap();
+    file.set_len(2 << 20).unwrap();
+    let db = db.to_str().unwrap();
+    proc.process(300, "app").fd(3, db);
+    let data = scan(&proc);
+
+    let path = db.strip_suffix(" (deleted)").unwrap();
+    let source: RecoverSource = path.parse().unwrap();
+    let dest = dir.path().join("recovered.db");
+    let recovered = data.recover(&source, &dest).unwrap();
+    assert_eq!(
+        (recovered.from.pid, recovered.from.role),
+        (300, FileRole::Fd(3))
+    );
+    assert_eq!(recovered.len, 2 << 20);
+    assert_eq!(fs::read(&dest).unwrap(), fs::read(db).unwrap());
+    assert!(fs::metadata(&dest).unwrap().blocks() <= fs::metadata(db).unwrap().blocks());
+    // Never overwrite
+    assert!(data.recover(&source, &dest).is_err());
+
+    let by_fd = dir.path().join("by-fd.db");
+    data.recover(&"300:3".parse().unwrap(), &by_fd).unwrap();
+    assert_eq!(fs::read(&by_fd).unwrap(), fs::read(db).unwrap());
+    let err = data.find_deleted(&"42:4".parse().unwrap()).unwrap_err();
+    assert!(err.to_string().contains("not deleted"), "{err}");
+    assert!(data.find_deleted(&"300:9".parse().unwrap()).is_err());
+    assert!("app.db".parse::<RecoverSource>().is_err());
+
+    // Copies aren't setuid, even when recovering as root
+    fs::set_permissions(db, fs::Permissions::from_mode(0o4750)).unwrap();
+    let plain = dir.path().join("plain.db");
+    copy_sparse(&fs::File::open(db).unwrap(), &plain).unwrap();
+    assert_eq!(fs::metadata(&plain).unwrap().mode() & 0o7777, 0o750);
+    // A failed copy is removed, so it can be retried
+    let failed = dir.path().join("failed");
+    assert!(copy_sparse(&fs::File::open(dir.path()).unwrap(), &failed).is_err());
+    assert!(!failed.exists());
+}
+
+#[test]
+fn test_find_under() {
+    let proc = fixture();
+    let mut data = scan(&proc);
+    assert!(data.find_under("/usr", true).is_err());
+    data.invert_pid_to_files("");
+    assert_eq!(
+        pids(data.find_under("/usr/lib", false).unwrap()),
+        [1, 42, 43]
+    );
+    // Only direct children without recursion
+    assert!(data.find_under("/usr", false).is_err());
+    assert_eq!(pids(data.find_under("/usr", true).unwrap()), [1, 42, 43]);
+    // The directory itself counts, here as a cwd
+    assert_eq!(pids(data.find_under("/srv/", false).unwrap()), [42, 43]);
+    assert_eq!(
+        data.files_under("/", false).unwrap(),
+        ["/", "/root", "/srv"].map(IStr::from)
+    );
+    assert!(data.find_under("/nonexistent", true).is_err());
+
+    data.retain_under("/run", false);
+    // Found through the path of its unix socket
+    assert_eq!(data.pid_to_files().keys().copied().collect_vec(), [42]);
+    assert_eq!(
+        data.pid_to_files()[&42].files.iter().collect_vec(),
+        [&IStr::from("socket:[2709]")]
+    );
 }
@@ -0,0 +1,95 @@
+use std::fs::read_to_string;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+
+use crate::{fmap, FMap, IStr};
+
+/// uid => user name, from `/etc/passwd`
+#[must_use]
+pub fn passwd() -> &'static FMap<u32, IStr> {
+    static PASSWD: OnceLock<FMap<u32, IStr>> = OnceLock::new();
+    PASSWD.get_or_init(|| {
+        read_to_string("/etc/passwd").map_or_else(|_| fmap(0), |content| parse_passwd(&content))
+    })
+}
+
+/// Parse the contents of `/etc/passwd`, the first name wins for duplicate uids
+#[must_use]
+pub fn parse_passwd(content: &str) -> FMap<u32, IStr> {
+    let mut users = fmap(0);
+    for line in content.lines().filter(|l| !l.starts_with('#')) {
+        // name:password:uid:gid:gecos:home:shell
+        let mut cols = line.split(':');
+        let (Some(name), Some(uid)) = (cols.next(), cols.nth(1)) else {
+            continue;
+        };
+        if let Ok(uid) = uid.parse() {
+            users.entry(uid).or_insert_with(|| name.into());
+        }
+    }
+    users
+}
+
+#[must_use]
+pub fn user_name(uid: u32) -> Option<&'static str> {
+    passwd().get(&uid).map(|name| &**name)
+}
+
+#[must_use]
+pub fn uid_by_name(name: &str) -> Option<u32> {
+    passwd()
+        .iter()
+        .find_map(|(&uid, n)| (&**n == name).then_some(uid))
+}
+
+/// The USER column, the user name or the uid if it has no name
+#[must_use]
+pub fn user_column(uid: Option<u32>) -> IStr {
+    match uid {
+        Some(uid) => passwd()
+            .get(&uid)
+            .cloned()
+            .unwrap_or_else(|| uid.to_string().into()),
+        None => "?".into(),
+    }
+}
+
+/// `--user` argument, a comma separated list of names or uids, `^` excludes a user
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct UserFilter {
+    pub include: Vec<u32>,
+    pub exclude: Vec<u32>,
+}
+
+impl UserFilter {
+    /// Whether a process owned by `uid` is selected
+    #[must_use]
+    pub fn matches(&self, uid: Option<u32>) -> bool {
+        let Some(uid) = uid else {
+            return self.include.is_empty();
+        };
+        (self.include.is_empty() || self.include.contains(&uid)) && !self.exclude.contains(&uid)
+    }
+}
+
+impl FromStr for UserFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut filter = UserFilter::default();
+        for user in s.split(',').filter(|u| !u.is_empty()) {
+            let (list, user) = match user.strip_prefix('^') {
+                Some(user) => (&mut filter.exclude, user),
+                None => (&mut filter.include, user),
+            };
+            let uid = match user.parse() {
+                Ok(uid) => uid,
+                Err(_) => uid_by_name(user).ok_or_else(|| anyhow!("unknown user {user}"))?,
+            };
+            list.push(uid);
+        }
+        Ok(filter)
+    }
+}
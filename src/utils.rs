@@ -10,14 +10,9 @@ pub fn leak_str(s: impl StrLeakExt) -> &'static str {
     StrLeakExt::leak_str(s)
 }
 
-#[macro_export]
-macro_rules! bfmt {
-    ($($args:tt)*) => {{
-        Box::leak(format!($($args)*).into_boxed_str()) as &'static str
-    }};
-}
-
 use std::io::BufWriter;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use fxhash::{FxHashMap, FxHashSet};
 pub type FSet<T> = FxHashSet<T>;
@@ -31,6 +26,90 @@ pub fn fset<V>(cap: usize) -> FSet<V> {
     FSet::with_capacity_and_hasher(cap, std::hash::BuildHasherDefault::default())
 }
 
+/// A shared string, deduplicated by an [`Interner`]
+pub type IStr = Arc<str>;
+
+/// Owns one copy of each string seen during a scan.
+///
+/// Processes share most of their files (libraries, `/dev/null`, ...), so they are stored once per [`crate::Data`]
+/// and freed with it, instead of being leaked.
+/// The strings are split by hash between shards, so the scan workers rarely wait on the same lock.
+#[derive(Default, Debug)]
+pub struct Interner {
+    shards: [Mutex<FSet<IStr>>; INTERNER_SHARDS],
+}
+
+const INTERNER_SHARDS: usize = 32;
+
+impl Interner {
+    /// The shared copy of `s`, added on first use
+    pub fn intern(&self, s: &str) -> IStr {
+        let mut strings = self.shard(s).lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(s) = strings.get(s) {
+            return s.clone();
+        }
+        let s = IStr::from(s);
+        strings.insert(s.clone());
+        s
+    }
+    fn shard(&self, s: &str) -> &Mutex<FSet<IStr>> {
+        // Middle bits, the table of the shard hashes with the low and top ones
+        #[allow(clippy::cast_possible_truncation)]
+        let i = (fxhash::hash64(s) >> 32) as usize % INTERNER_SHARDS;
+        &self.shards[i]
+    }
+    /// Number of distinct strings
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 pub fn buf_stdout<'a>(all: impl ExactSizeIterator) -> BufWriter<std::io::StdoutLock<'a>> {
     BufWriter::with_capacity((all.len() * 80 / 8).min(8192), std::io::stdout().lock())
 }
+
+/// Width of the terminal on stdout, `None` if stdout is not a terminal
+#[must_use]
+pub fn terminal_width() -> Option<usize> {
+    use std::io::IsTerminal;
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a winsize into the pointer
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+/// `time` broken down in the local timezone
+#[must_use]
+pub fn local_time(time: SystemTime) -> libc::tm {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => libc::time_t::try_from(since.as_secs()).unwrap_or(libc::time_t::MAX),
+        Err(before) => {
+            libc::time_t::try_from(before.duration().as_secs()).map_or(libc::time_t::MIN, |s| -s)
+        }
+    };
+    // SAFETY: localtime_r only writes into tm, a zeroed tm is valid
+    unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&raw const secs, &raw mut tm);
+        tm
+    }
+}
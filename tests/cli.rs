@@ -0,0 +1,437 @@
+#[path = "../src/fixture.rs"]
+mod fixture;
+
+use std::process::Command;
+
+use fixture::ProcFixture;
+
+fn fixture() -> ProcFixture {
+    let proc = ProcFixture::new();
+    proc.process(1, "init")
+        .cwd("/")
+        .exe("/sbin/init")
+        .fd(0, "/dev/null");
+    for pid in [42, 43] {
+        proc.process(pid, "nginx")
+            .cwd("/srv")
+            .exe("/usr/sbin/nginx")
+            .fd_with(4, "/var/log/access.log", 10, 0o2001)
+            .map("/usr/lib/libc.so.6");
+    }
+    proc
+}
+
+fn lsof(proc: &ProcFixture, args: &[&str]) -> String {
+    let out = Command::new(env!("CARGO_BIN_EXE_lsof"))
+        .args(["--proc-root", proc.root()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn lines(out: &str) -> Vec<&str> {
+    out.lines().collect()
+}
+
+#[test]
+fn no_grouping() {
+    let proc = fixture();
+    let out = lsof(
+        &proc,
+        &[
+            "-s",
+            "pid,filename",
+            "-o",
+            "ascending",
+            "--columns",
+            "command,pid,fd,name",
+        ],
+    );
+    assert_eq!(
+        lines(&out),
+        [
+            "COMMAND PID FD  NAME",
+            "init      1 cwd /",
+            "init      1 0r  /dev/null",
+            "init      1 txt /sbin/init",
+            "nginx    42 cwd /srv",
+            "nginx    42 mem /usr/lib/libc.so.6",
+            "nginx    42 txt /usr/sbin/nginx",
+            "nginx    42 4w  /var/log/access.log",
+            "nginx    43 cwd /srv",
+            "nginx    43 mem /usr/lib/libc.so.6",
+            "nginx    43 txt /usr/sbin/nginx",
+            "nginx    43 4w  /var/log/access.log",
+        ]
+    );
+}
+
+#[test]
+fn group_by_file() {
+    let proc = fixture();
+    let out = lsof(&proc, &["-g", "file", "-s", "filename", "-o", "ascending"]);
+    assert_eq!(
+        lines(&out),
+        [
+            "/ 1 init",
+            "/dev/null 1 init",
+            "/sbin/init 1 init",
+            "/srv 2 nginx",
+            "/usr/lib/libc.so.6 2 nginx",
+            "/usr/sbin/nginx 2 nginx",
+            "/var/log/access.log 2 nginx",
+        ]
+    );
+    let out = lsof(
+        &proc,
+        &[
+            "-g",
+            "file",
+            "-G",
+            "list",
+            "-s",
+            "filename",
+            "-o",
+            "ascending",
+        ],
+    );
+    assert_eq!(lines(&out)[3], "/srv nginx(42),nginx(43)");
+}
+
+#[test]
+fn group_by_pid() {
+    let proc = fixture();
+    let out = lsof(&proc, &["-g", "pid", "-s", "pid", "-o", "ascending"]);
+    let user = lines(&out)[0].split(' ').nth(2).unwrap().to_owned();
+    assert_eq!(
+        lines(&out),
+        [
+            format!("1 init {user} 3"),
+            format!("42 nginx {user} 4"),
+            format!("43 nginx {user} 4"),
+        ]
+    );
+}
+
+#[test]
+fn group_by_filetype() {
+    let proc = fixture();
+    let out = lsof(
+        &proc,
+        &["-g", "filetype", "-s", "filetype", "-o", "ascending"],
+    );
+    assert_eq!(lines(&out), [".6 2 2", ".log 2 2", "<none> 6 3", "dev 1 1"]);
+}
+
+#[test]
+fn group_by_proc_name() {
+    let proc = fixture();
+    let out = lsof(
+        &proc,
+        &["-g", "proc-name", "-s", "proc-name", "-o", "ascending"],
+    );
+    assert_eq!(lines(&out), ["init 3", "nginx 8"]);
+    let out = lsof(
+        &proc,
+        &["-g", "proc-name", "-s", "n-pids", "-o", "ascending"],
+    );
+    assert_eq!(lines(&out), ["init 1 3", "nginx 2 8"]);
+}
+
+#[test]
+fn group_by_user() {
+    let proc = fixture();
+    // Every process of the fixture is owned by whoever runs the tests
+    let out = lsof(&proc, &["-g", "user", "-G", "list"]);
+    let (_user, pids) = out.trim_end().split_once(' ').unwrap();
+    assert_eq!(pids, "1,42,43");
+}
+
+#[test]
+fn fields_and_filters() {
+    let proc = fixture();
+    let out = lsof(&proc, &["-p", "1", "-t", "fd", "--columns", "pid,tid,name"]);
+    assert_eq!(lines(&out), ["PID TID NAME", "  1   1 /dev/null"]);
+    let out = lsof(&proc, &["-p", "1", "-t", "fd", "-F", "pcn"]);
+    assert_eq!(out, "p1\ncinit\nf0\nn/dev/null\n");
+    // Like lsof `-Fp`, only the pids
+    assert_eq!(lsof(&proc, &["-F", "p"]), "p1\np42\np43\n");
+    // The process fields don't need any file
+    proc.process(3, "nofiles");
+    assert_eq!(lsof(&proc, &["-p", "3", "-F", "pcR"]), "p3\ncnofiles\nR1\n");
+    let out = lsof(&proc, &["-g", "pid", "-s", "pid", "--file-regex", "access"]);
+    assert_eq!(lines(&out).len(), 2);
+    // Filters combine, a file is listed if it matches all of them
+    let out = lsof(
+        &proc,
+        &["-p", "42", "-P", "^ng", "--file-regex", "log", "-F", "pn"],
+    );
+    assert_eq!(out, "p42\nf4\nn/var/log/access.log\n");
+    assert_eq!(lsof(&proc, &["-p", "1", "-P", "^ng", "-F", "pn"]), "");
+    let out = lsof(
+        &proc,
+        &[
+            "-f",
+            "/srv",
+            "-s",
+            "pid",
+            "-o",
+            "ascending",
+            "--columns",
+            "pid,name",
+        ],
+    );
+    assert_eq!(lines(&out), ["PID NAME", " 42 /srv", " 43 /srv"]);
+}
+
+#[test]
+fn repeat_until_empty() {
+    let proc = fixture();
+    // `+r` stops at the first scan that lists nothing
+    assert_eq!(lsof(&proc, &["-r", "+0", "-p", "7"]), "");
+    // Judged on the files listed, which `--file` selects
+    assert_eq!(lsof(&proc, &["-r", "+0", "-f", "/nonexistent"]), "");
+    let out = Command::new(env!("CARGO_BIN_EXE_lsof"))
+        .args(["--proc-root", proc.root(), "-r", "1s"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn repeat_reader_gone() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let proc = fixture();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lsof"))
+        .args(["--proc-root", proc.root(), "-r", "1", "-p", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut first = String::new();
+    // The separator, the header and the 3 files of init
+    for _ in 0..5 {
+        stdout.read_line(&mut first).unwrap();
+    }
+    assert!(first.starts_with("======= "), "{first}");
+    // Like `lsof -r 1 | head -5`, writing the next separator fails instead of panicking
+    drop(stdout);
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Broken pipe") && !stderr.contains("panicked"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn save_load_diff() {
+    let proc = fixture();
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.bin");
+    let new = dir.path().join("new.bin");
+    let old = old.to_str().unwrap();
+    let new = new.to_str().unwrap();
+    let fields = lsof(&proc, &["--save", old, "-F", "pfn"]);
+    // A snapshot lists the same files as the scan it was saved from
+    assert_eq!(lsof(&proc, &["--load", old, "-F", "pfn"]), fields);
+    assert_eq!(
+        lsof(&proc, &["--load", old, "-p", "1", "-t", "fd", "-F", "pn"]),
+        "p1\nf0\nn/dev/null\n"
+    );
+    // Not read from whatever now has the pid
+    let out = lsof(
+        &proc,
+        &[
+            "--load",
+            old,
+            "-p",
+            "1",
+            "-t",
+            "fd",
+            "--columns",
+            "type,node,name",
+        ],
+    );
+    assert_eq!(lines(&out), ["TYPE    NODE NAME", "unknown      /dev/null"]);
+
+    std::fs::remove_dir_all(proc.process(43, "nginx").path()).unwrap();
+    proc.process(7, "sleep").cwd("/tmp").fd(1, "/tmp/out");
+    lsof(&proc, &["--save", new]);
+    let out = lsof(&proc, &["--color", "never", "diff", old, new]);
+    assert_eq!(
+        lines(&out),
+        [
+            "7 sleep started",
+            "  + /tmp",
+            "  + /tmp/out",
+            "43 nginx exited",
+            "  - /srv",
+            "  - /usr/lib/libc.so.6",
+            "  - /usr/sbin/nginx",
+            "  - /var/log/access.log",
+        ]
+    );
+    let out = lsof(&proc, &["--format", "csv", "-p", "7", "diff", old, new]);
+    assert_eq!(
+        lines(&out),
+        [
+            "pid,proc,process,opened,closed",
+            "7,sleep,started,\"/tmp,/tmp/out\",",
+        ]
+    );
+}
+
+#[test]
+fn deleted_and_pinned() {
+    let proc = fixture();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log (deleted)");
+    std::fs::write(&log, "still here\n").unwrap();
+    let log = log.to_str().unwrap();
+    proc.process(7, "app").fd(1, log);
+    let out = lsof(&proc, &["--deleted", "-F", "pn"]);
+    assert_eq!(out, format!("p7\nf1\nn{log}\n"));
+
+    let out = lsof(&proc, &["--pinned", "--format", "csv"]);
+    let out = lines(&out);
+    assert_eq!(out[0], "filesystem,device,pid,proc,files,bytes");
+    assert_eq!(out.len(), 3);
+    assert!(out[2].starts_with(",,7,app,1,"), "{}", out[2]);
+}
+
+#[test]
+fn recover() {
+    let proc = fixture();
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("app.log (deleted)");
+    std::fs::write(&log, "still here\n").unwrap();
+    proc.process(7, "app").fd(1, log.to_str().unwrap());
+    let dest = dir.path().join("app.log");
+    let dest = dest.to_str().unwrap();
+    let out = lsof(&proc, &["recover", "7:1", dest]);
+    assert!(out.starts_with("recovered "), "{out}");
+    assert_eq!(std::fs::read_to_string(dest).unwrap(), "still here\n");
+    // The destination is never overwritten
+    let out = Command::new(env!("CARGO_BIN_EXE_lsof"))
+        .args(["--proc-root", proc.root(), "recover", "7:1", dest])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
+
+#[test]
+fn dir() {
+    let proc = fixture();
+    let out = lsof(&proc, &["--dir", "/usr/lib", "-F", "pn"]);
+    assert_eq!(
+        out,
+        "p42\nfmem\nn/usr/lib/libc.so.6\np43\nfmem\nn/usr/lib/libc.so.6\n"
+    );
+    let out = lsof(
+        &proc,
+        &["--dir-recursive", "/var", "-g", "pid", "-G", "list"],
+    );
+    assert_eq!(lines(&out).len(), 2);
+    assert_eq!(lsof(&proc, &["--dir", "/var", "-F", "pn"]), "");
+}
+
+#[test]
+fn process_columns() {
+    let proc = fixture();
+    let out = Command::new(env!("CARGO_BIN_EXE_lsof"))
+        .args(["--proc-root", proc.root(), "-p", "42", "-t", "fd"])
+        .args(["--columns", "pid,ppid,state,start,name"])
+        .env("TZ", "UTC")
+        .output()
+        .unwrap();
+    // The fixture booted at 1700000000, on 2023-11-14
+    assert_eq!(
+        lines(&String::from_utf8(out.stdout).unwrap()),
+        [
+            "PID PPID STATE    START NAME",
+            " 42    1 sleeping Nov14 /var/log/access.log",
+        ]
+    );
+}
+
+#[test]
+fn csv() {
+    let proc = fixture();
+    proc.process(7, "app").fd(1, "/tmp/a,\"b\"");
+    let out = lsof(
+        &proc,
+        &["--format", "csv", "-p", "7", "--columns", "pid,name"],
+    );
+    assert_eq!(lines(&out), ["PID,NAME", "7,\"/tmp/a,\"\"b\"\"\""]);
+    // The header is written even if nothing matched
+    let out = lsof(&proc, &["--format", "csv", "-g", "pid", "-p", "8"]);
+    assert_eq!(lines(&out), ["pid,proc,user,nfiles,files"]);
+    let out = lsof(
+        &proc,
+        &["--format", "tsv", "-p", "8", "--columns", "pid,name"],
+    );
+    assert_eq!(lines(&out), ["PID\tNAME"]);
+}
+
+#[test]
+fn reverse_sort_keys() {
+    let proc = fixture();
+    let columns = ["-o", "ascending", "--columns", "pid,name"];
+    // Ties on the pid are broken by the filename, reversed
+    let out = lsof(&proc, &[&["-s", "pid,-filename"][..], &columns].concat());
+    assert_eq!(
+        lines(&out),
+        [
+            "PID NAME",
+            "  1 /sbin/init",
+            "  1 /dev/null",
+            "  1 /",
+            " 42 /var/log/access.log",
+            " 42 /usr/sbin/nginx",
+            " 42 /usr/lib/libc.so.6",
+            " 42 /srv",
+            " 43 /var/log/access.log",
+            " 43 /usr/sbin/nginx",
+            " 43 /usr/lib/libc.so.6",
+            " 43 /srv",
+        ]
+    );
+    let out = lsof(
+        &proc,
+        &[&["-s", "-pid", "-t", "cwd"][..], &columns].concat(),
+    );
+    assert_eq!(lines(&out), ["PID NAME", " 43 /srv", " 42 /srv", "  1 /"]);
+}
+
+#[test]
+fn group_fold_first_and_sample() {
+    let proc = fixture();
+    let sorted = ["-s", "filename", "-o", "ascending"];
+    let out = lsof(
+        &proc,
+        &[&["-g", "file", "-G", "first"][..], &sorted].concat(),
+    );
+    assert_eq!(lines(&out)[3], "/srv nginx(42)");
+    let out = lsof(
+        &proc,
+        &[&["-g", "file", "-G", "sample", "-n", "2"][..], &sorted].concat(),
+    );
+    assert_eq!(lines(&out)[3], "/srv nginx(42),nginx(43)");
+    // The sample size defaults to 3
+    let out = lsof(&proc, &["-g", "pid", "-G", "sample", "-p", "42"]);
+    let files = lines(&out)[0].rsplit(' ').next().unwrap().to_owned();
+    assert_eq!(files, "/srv,/usr/lib/libc.so.6,/usr/sbin/nginx");
+    let out = lsof(&proc, &["-g", "pid", "-G", "sample", "-n", "1", "-p", "42"]);
+    assert!(out.trim_end().ends_with(" /srv"), "{out}");
+}